@@ -0,0 +1,88 @@
+use std::collections::{HashMap, VecDeque};
+
+/// 入れた順に古いものから忘れる上限付きの表（常駐し続けても増え続けないように）
+pub struct BoundedMap<V> {
+    entries: HashMap<String, V>,
+    order: VecDeque<String>, // 古い順
+    capacity: usize,
+}
+
+impl<V> BoundedMap<V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        self.entries.get(key)
+    }
+
+    /// 値を入れる（既にあれば置き換え、入れた順は変えない）
+    pub fn insert(&mut self, key: String, value: V) {
+        self.remember(&key);
+        self.entries.insert(key, value);
+    }
+
+    /// 値を取り出す（なければ既定値を入れる）
+    pub fn entry_or_default(&mut self, key: String) -> &mut V
+    where
+        V: Default,
+    {
+        self.remember(&key);
+        self.entries.entry(key).or_default()
+    }
+
+    /// 新しいキーなら順番を記録し、上限を超えたら最も古いものを忘れる
+    fn remember(&mut self, key: &str) {
+        if self.entries.contains_key(key) {
+            return;
+        }
+        self.order.push_back(key.to_string());
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forgets_oldest_beyond_capacity() {
+        let mut map = BoundedMap::new(2);
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        map.insert("c".to_string(), 3);
+        assert_eq!(map.get("a"), None);
+        assert_eq!(map.get("b"), Some(&2));
+        assert_eq!(map.get("c"), Some(&3));
+    }
+
+    #[test]
+    fn replacing_keeps_insertion_order() {
+        let mut map = BoundedMap::new(2);
+        map.insert("a".to_string(), 1);
+        map.insert("b".to_string(), 2);
+        map.insert("a".to_string(), 10);
+        map.insert("c".to_string(), 3);
+        assert_eq!(map.get("a"), None);
+        assert_eq!(map.get("b"), Some(&2));
+    }
+
+    #[test]
+    fn entry_or_default_inserts_and_updates() {
+        let mut map: BoundedMap<Vec<u32>> = BoundedMap::new(2);
+        map.entry_or_default("a".to_string()).push(1);
+        map.entry_or_default("a".to_string()).push(2);
+        assert_eq!(map.get("a"), Some(&vec![1, 2]));
+        map.entry_or_default("b".to_string());
+        map.entry_or_default("c".to_string());
+        assert_eq!(map.get("a"), None);
+    }
+}
@@ -0,0 +1,143 @@
+/// 見た目が紛らわしい文字の対応表（小文字にした後の文字を右の文字とみなす）
+const CONFUSABLES: &[(char, char)] = &[
+    // 数字・記号とラテン文字
+    ('0', 'o'),
+    ('1', 'l'),
+    ('|', 'l'),
+    // キリル文字
+    ('а', 'a'),
+    ('в', 'b'),
+    ('е', 'e'),
+    ('к', 'k'),
+    ('м', 'm'),
+    ('н', 'h'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('т', 't'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('ѕ', 's'),
+    // ギリシャ文字
+    ('α', 'a'),
+    ('β', 'b'),
+    ('ε', 'e'),
+    ('ι', 'i'),
+    ('κ', 'k'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+    ('τ', 't'),
+    ('υ', 'u'),
+    ('χ', 'x'),
+    // カタカナ・ひらがなと漢字
+    ('へ', 'ヘ'),
+    ('ぺ', 'ペ'),
+    ('べ', 'ベ'),
+    ('一', 'ー'),
+    ('―', 'ー'),
+    ('—', 'ー'),
+    ('口', 'ロ'),
+    ('力', 'カ'),
+    ('工', 'エ'),
+    ('二', 'ニ'),
+    ('八', 'ハ'),
+    ('卜', 'ト'),
+    ('夕', 'タ'),
+    ('才', 'オ'),
+];
+
+/// 見た目が同じになる文字列は同じ値になるように正規化する
+/// 空白や不可視文字を除き、全角英数を半角にして小文字にし、紛らわしい文字を代表の文字に置き換える
+pub fn skeleton(name: &str) -> String {
+    let mapped: String = name
+        .chars()
+        .filter(|c| !c.is_whitespace() && !is_invisible(*c))
+        .map(to_halfwidth)
+        // 大文字の I は小文字の l と見分けにくい（小文字にすると区別できなくなるので先に置き換える）
+        .map(|c| if c == 'I' { 'l' } else { c })
+        .flat_map(char::to_lowercase)
+        .map(|c| {
+            CONFUSABLES
+                .iter()
+                .find(|(from, _)| *from == c)
+                .map(|(_, to)| *to)
+                .unwrap_or(c)
+        })
+        .collect();
+    // 2文字で1文字に見えるもの
+    mapped.replace("rn", "m").replace("vv", "w")
+}
+
+/// 2つの名前が見分けにくいか（空の名前は比べない）
+pub fn looks_alike(a: &str, b: &str) -> bool {
+    let a = skeleton(a);
+    !a.is_empty() && a == skeleton(b)
+}
+
+/// 全角の英数字・記号を半角に
+fn to_halfwidth(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// ゼロ幅文字や書式制御文字
+fn is_invisible(c: char) -> bool {
+    matches!(
+        c,
+        '\u{200B}'..='\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2060}'..='\u{2064}' | '\u{FEFF}' | '\u{00AD}'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latin_lookalikes_share_a_skeleton() {
+        assert_eq!(skeleton("Alice"), "alice");
+        assert_eq!(skeleton("аlice"), "alice"); // キリル文字の а
+        assert_eq!(skeleton("αlice"), "alice"); // ギリシャ文字の α
+        assert_eq!(skeleton("ａｌｉｃｅ"), "alice"); // 全角
+        assert_eq!(skeleton("ＡＬＩＣＥ"), skeleton("ALICE"));
+        assert_eq!(skeleton("0live"), skeleton("olive"));
+    }
+
+    #[test]
+    fn capital_i_and_digit_one_look_like_small_l() {
+        assert_eq!(skeleton("Ilya"), skeleton("llya"));
+        assert_eq!(skeleton("1lya"), skeleton("llya"));
+        assert_eq!(skeleton("|lya"), skeleton("llya"));
+    }
+
+    #[test]
+    fn letter_pairs_that_look_like_one_letter() {
+        assert_eq!(skeleton("rnoon"), skeleton("moon"));
+        assert_eq!(skeleton("vvave"), skeleton("wave"));
+    }
+
+    #[test]
+    fn whitespace_and_invisible_characters_are_ignored() {
+        assert_eq!(skeleton("al ice"), "alice");
+        assert_eq!(skeleton("al\u{200B}ice\u{FEFF}"), "alice");
+        assert_eq!(skeleton("\u{202E}alice"), "alice");
+    }
+
+    #[test]
+    fn kana_and_kanji_lookalikes_share_a_skeleton() {
+        assert_eq!(skeleton("へ口一"), skeleton("ヘロー"));
+        assert_eq!(skeleton("力工"), skeleton("カエ"));
+    }
+
+    #[test]
+    fn looks_alike_compares_skeletons() {
+        assert!(looks_alike("gilga", "giIga"));
+        assert!(!looks_alike("alice", "alicia"));
+        assert!(!looks_alike("", ""));
+        assert!(!looks_alike("\u{200B}", ""));
+    }
+}
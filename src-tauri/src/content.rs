@@ -0,0 +1,285 @@
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// 本文を種類ごとに分割したセグメント（NIP-21 / NIP-27 / NIP-30）
+#[derive(Clone, Serialize, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentSegment {
+    /// 普通のテキスト
+    Text { text: String },
+    /// http(s) のURL
+    Url { url: String },
+    /// ユーザーへの言及（nostr:npub / nostr:nprofile）
+    MentionProfile { pubkey: String, name: String },
+    /// 投稿への言及（nostr:note / nostr:nevent）
+    MentionEvent {
+        id: String,
+        author: Option<String>,
+        relays: Vec<String>,
+    },
+    /// ハッシュタグ（# は含まない）
+    Hashtag { tag: String },
+    /// カスタム絵文字（emoji タグで定義された :shortcode:）
+    CustomEmoji { shortcode: String, url: String },
+}
+
+/// URL末尾から取り除く句読点
+const URL_TRAILING_PUNCTUATION: &[char] = &['.', ',', '!', '?', ')', ']', '」', '。', '、', '"', '\''];
+
+/// イベントの emoji タグ（shortcode → 画像URL）を集める
+pub fn emoji_tags(event: &Event) -> HashMap<String, String> {
+    event
+        .tags
+        .iter()
+        .filter_map(|tag| {
+            let tag_vec = tag.clone().to_vec();
+            if tag_vec.len() >= 3 && tag_vec[0] == "emoji" {
+                Some((tag_vec[1].clone(), tag_vec[2].clone()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// 本文をセグメントに分割（resolve_name は pubkey から表示名を引く関数）
+pub fn parse<F>(content: &str, emojis: &HashMap<String, String>, resolve_name: F) -> Vec<ContentSegment>
+where
+    F: Fn(&PublicKey) -> String,
+{
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < content.len() {
+        let rest = &content[i..];
+        let prev = content[..i].chars().next_back();
+
+        if let Some((segment, len)) = parse_token(rest, prev, emojis, &resolve_name) {
+            if !text.is_empty() {
+                segments.push(ContentSegment::Text {
+                    text: std::mem::take(&mut text),
+                });
+            }
+            segments.push(segment);
+            i += len;
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap_or_default();
+        text.push(c);
+        i += c.len_utf8();
+    }
+
+    if !text.is_empty() {
+        segments.push(ContentSegment::Text { text });
+    }
+
+    segments
+}
+
+/// 先頭にあるトークンを1つ解釈（セグメントと消費したバイト数）
+fn parse_token<F>(
+    rest: &str,
+    prev: Option<char>,
+    emojis: &HashMap<String, String>,
+    resolve_name: &F,
+) -> Option<(ContentSegment, usize)>
+where
+    F: Fn(&PublicKey) -> String,
+{
+    // URL
+    if rest.starts_with("https://") || rest.starts_with("http://") {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = rest[..end].trim_end_matches(URL_TRAILING_PUNCTUATION);
+        return Some((ContentSegment::Url { url: url.to_string() }, url.len()));
+    }
+
+    // nostr: 参照（NIP-21）
+    if let Some(bech32) = rest.strip_prefix("nostr:") {
+        let len = bech32
+            .find(|c: char| !c.is_ascii_lowercase() && !c.is_ascii_digit())
+            .unwrap_or(bech32.len());
+        let segment = match Nip19::from_bech32(&bech32[..len]).ok()? {
+            Nip19::Pubkey(public_key) => ContentSegment::MentionProfile {
+                pubkey: public_key.to_hex(),
+                name: resolve_name(&public_key),
+            },
+            Nip19::Profile(profile) => ContentSegment::MentionProfile {
+                pubkey: profile.public_key.to_hex(),
+                name: resolve_name(&profile.public_key),
+            },
+            Nip19::EventId(event_id) => ContentSegment::MentionEvent {
+                id: event_id.to_hex(),
+                author: None,
+                relays: vec![],
+            },
+            Nip19::Event(event) => ContentSegment::MentionEvent {
+                id: event.event_id.to_hex(),
+                author: event.author.map(|pk| pk.to_hex()),
+                relays: event.relays.iter().map(|r| r.to_string()).collect(),
+            },
+            _ => return None,
+        };
+        return Some((segment, "nostr:".len() + len));
+    }
+
+    // ハッシュタグ（行頭か空白の直後のみ）
+    if let Some(word) = rest.strip_prefix('#') {
+        if prev.is_some_and(|c| !c.is_whitespace()) {
+            return None;
+        }
+        let len = word
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(word.len());
+        if len == 0 {
+            return None;
+        }
+        let tag = word[..len].to_string();
+        return Some((ContentSegment::Hashtag { tag }, 1 + len));
+    }
+
+    // カスタム絵文字（NIP-30）
+    if let Some(after) = rest.strip_prefix(':') {
+        let end = after.find(':')?;
+        let shortcode = &after[..end];
+        let url = emojis.get(shortcode)?;
+        return Some((
+            ContentSegment::CustomEmoji {
+                shortcode: shortcode.to_string(),
+                url: url.clone(),
+            },
+            end + 2,
+        ));
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> ContentSegment {
+        ContentSegment::Text { text: text.to_string() }
+    }
+
+    fn parse_plain(content: &str) -> Vec<ContentSegment> {
+        parse(content, &HashMap::new(), |_| "someone".to_string())
+    }
+
+    #[test]
+    fn plain_text_is_one_segment() {
+        assert_eq!(parse_plain("こんにちは world"), vec![text("こんにちは world")]);
+        assert!(parse_plain("").is_empty());
+    }
+
+    #[test]
+    fn urls_drop_trailing_punctuation() {
+        assert_eq!(
+            parse_plain("see https://example.com/a?b=1. ok"),
+            vec![
+                text("see "),
+                ContentSegment::Url {
+                    url: "https://example.com/a?b=1".to_string()
+                },
+                text(". ok"),
+            ]
+        );
+        assert_eq!(
+            parse_plain("見て http://example.com/」。"),
+            vec![
+                text("見て "),
+                ContentSegment::Url {
+                    url: "http://example.com/".to_string()
+                },
+                text("」。"),
+            ]
+        );
+    }
+
+    #[test]
+    fn hashtags_only_at_word_start() {
+        assert_eq!(
+            parse_plain("#nostr と#tag #日本語"),
+            vec![
+                ContentSegment::Hashtag { tag: "nostr".to_string() },
+                text(" と#tag "),
+                ContentSegment::Hashtag { tag: "日本語".to_string() },
+            ]
+        );
+        assert_eq!(parse_plain("# alone"), vec![text("# alone")]);
+    }
+
+    #[test]
+    fn profile_mentions_resolve_names() {
+        let keys = Keys::generate();
+        let content = format!("hi nostr:{}!", keys.public_key().to_bech32().unwrap());
+        let segments = parse(&content, &HashMap::new(), |pubkey| format!("name-{}", &pubkey.to_hex()[..4]));
+        assert_eq!(
+            segments,
+            vec![
+                text("hi "),
+                ContentSegment::MentionProfile {
+                    pubkey: keys.public_key().to_hex(),
+                    name: format!("name-{}", &keys.public_key().to_hex()[..4]),
+                },
+                text("!"),
+            ]
+        );
+    }
+
+    #[test]
+    fn note_mentions_become_event_segments() {
+        let event_id = EventId::from_hex(&"ab".repeat(32)).unwrap();
+        let content = format!("nostr:{}", event_id.to_bech32().unwrap());
+        assert_eq!(
+            parse_plain(&content),
+            vec![ContentSegment::MentionEvent {
+                id: event_id.to_hex(),
+                author: None,
+                relays: vec![],
+            }]
+        );
+    }
+
+    #[test]
+    fn invalid_nostr_references_stay_text() {
+        assert_eq!(parse_plain("nostr:npub1invalid"), vec![text("nostr:npub1invalid")]);
+    }
+
+    #[test]
+    fn custom_emoji_needs_an_emoji_tag() {
+        let emojis = HashMap::from([("gilga".to_string(), "https://example.com/gilga.png".to_string())]);
+        let segments = parse(":gilga: :unknown:", &emojis, |_| String::new());
+        assert_eq!(
+            segments,
+            vec![
+                ContentSegment::CustomEmoji {
+                    shortcode: "gilga".to_string(),
+                    url: "https://example.com/gilga.png".to_string(),
+                },
+                text(" :unknown:"),
+            ]
+        );
+    }
+
+    #[test]
+    fn emoji_tags_are_collected_from_event() {
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note(":gilga:")
+            .tags(vec![Tag::parse(vec![
+                "emoji".to_string(),
+                "gilga".to_string(),
+                "https://example.com/gilga.png".to_string(),
+            ])
+            .unwrap()])
+            .sign_with_keys(&keys)
+            .unwrap();
+        assert_eq!(
+            emoji_tags(&event),
+            HashMap::from([("gilga".to_string(), "https://example.com/gilga.png".to_string())])
+        );
+    }
+}
@@ -1,10 +1,25 @@
-use nostr_client::{NostrMessage, NostrState};
+use nip05::Nip05Lookup;
+use nip11::RelayInfo;
+use nostr_client::{
+    AppSettings, AuthPolicy, ContentWarningMode, Conversation, DirectMessage, IdentityInfo, KeyStatus, Notification,
+    NostrEvent, NostrState, RelayEntry, RelayStatusReport,
+};
 use serde::Serialize;
 use std::sync::Arc;
+use tauri::menu::{CheckMenuItem, Menu};
 use tauri::{Emitter, Manager, Runtime, State};
 use tokio::sync::{mpsc, RwLock};
 
+mod bounded;
+mod confusable;
+mod content;
+mod nip05;
+mod nip11;
 mod nostr_client;
+mod outbox;
+mod profiles;
+mod relay_url;
+mod storage;
 
 /// フロントエンドに返すメッセージ
 #[derive(Clone, Serialize)]
@@ -18,12 +33,73 @@ pub struct Message {
     context: Option<String>,
 }
 
+/// トレイメニューからの切り替えに失敗したことの通知（パスフレーズが要るならフロントエンドで尋ねる）
+#[derive(Clone, Serialize)]
+struct IdentitySwitchFailed {
+    id: String,
+    name: String,
+    locked: bool,
+    message: String,
+}
+
 /// アプリケーション状態
 pub struct AppState {
     nostr: Arc<NostrState>,
     messages: Arc<RwLock<Vec<Message>>>,
 }
 
+/// 未読通知数をトレイに反映し、オーバーレイが隠れていれば注意を引く
+fn notify_unread<R: Runtime>(app: &tauri::AppHandle<R>, unread: usize) {
+    if let Some(tray) = app.tray_by_id("main") {
+        let tooltip = if unread > 0 {
+            format!("gilga ({})", unread)
+        } else {
+            "gilga".to_string()
+        };
+        let _ = tray.set_tooltip(Some(tooltip));
+    }
+    if unread > 0 {
+        if let Some(window) = app.get_webview_window("main") {
+            if !window.is_visible().unwrap_or(false) {
+                let _ = window.request_user_attention(Some(tauri::UserAttentionType::Informational));
+            }
+        }
+    }
+}
+
+/// トレイメニューのアイデンティティ項目IDの接頭辞
+const IDENTITY_MENU_PREFIX: &str = "identity:";
+
+/// トレイメニューを作り直す（アイデンティティの切り替え用）
+async fn refresh_tray_menu<R: Runtime>(app: &tauri::AppHandle<R>, nostr: &NostrState) {
+    let identities = nostr.list_identities().await;
+    let Some(tray) = app.tray_by_id("main") else {
+        return;
+    };
+
+    let menu = Menu::new(app).and_then(|menu| {
+        for identity in &identities {
+            let item = CheckMenuItem::with_id(
+                app,
+                format!("{}{}", IDENTITY_MENU_PREFIX, identity.id),
+                &identity.name,
+                true,
+                identity.active,
+                None::<&str>,
+            )?;
+            menu.append(&item)?;
+        }
+        Ok(menu)
+    });
+
+    match menu {
+        Ok(menu) => {
+            let _ = tray.set_menu(Some(menu));
+        }
+        Err(e) => println!("⚠️ Failed to build tray menu: {}", e),
+    }
+}
+
 /// オーバーレイの表示/非表示を切り替え
 fn toggle_overlay<R: Runtime>(app: &tauri::AppHandle<R>) {
     if let Some(window) = app.get_webview_window("main") {
@@ -42,6 +118,10 @@ async fn connect(
     app: tauri::AppHandle,
     state: State<'_, AppState>,
 ) -> Result<String, String> {
+    // イベント受信用チャンネルを設定（init 中に取得した自分のプロフィールも転送できるよう先に作る）
+    let (tx, mut rx) = mpsc::unbounded_channel::<NostrEvent>();
+    state.nostr.set_event_sender(tx).await;
+
     state
         .nostr
         .init()
@@ -54,10 +134,6 @@ async fn connect(
         .await
         .map_err(|e| format!("購読エラー: {}", e))?;
 
-    // イベント受信用チャンネルを設定
-    let (tx, mut rx) = mpsc::unbounded_channel::<NostrMessage>();
-    state.nostr.set_event_sender(tx).await;
-
     // イベントリスニング開始
     state
         .nostr
@@ -68,8 +144,26 @@ async fn connect(
     // フロントエンドへのイベント転送タスク
     let app_handle = app.clone();
     tokio::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            let _ = app_handle.emit("nostr-message", msg);
+        while let Some(event) = rx.recv().await {
+            let _ = match event {
+                NostrEvent::Message(msg) => app_handle.emit("nostr-message", msg),
+                NostrEvent::Deleted(deleted) => app_handle.emit("message-deleted", deleted),
+                NostrEvent::Notification(notification) => {
+                    notify_unread(&app_handle, notification.unread);
+                    app_handle.emit("notification", notification)
+                }
+                NostrEvent::DirectMessage(dm) => app_handle.emit("direct-message", dm),
+                NostrEvent::IdentityChanged(identity) => {
+                    let nostr = app_handle.state::<AppState>().nostr.clone();
+                    refresh_tray_menu(&app_handle, &nostr).await;
+                    app_handle.emit("identity-changed", identity)
+                }
+                NostrEvent::MyProfile(profile) => app_handle.emit("my-profile-updated", profile),
+                NostrEvent::AuthorUpdated(author) => app_handle.emit("author-updated", author),
+                NostrEvent::Nip05Verified(verified) => app_handle.emit("nip05-verified", verified),
+                NostrEvent::RelayListFound(offer) => app_handle.emit("relay-list-found", offer),
+                NostrEvent::RelayStatus(status) => app_handle.emit("relay-status", status),
+            };
         }
     });
 
@@ -80,16 +174,76 @@ async fn connect(
 
 /// メッセージを送信
 #[tauri::command]
-async fn send_message(content: String, state: State<'_, AppState>) -> Result<String, String> {
+async fn send_message(
+    content: String,
+    content_warning: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
     let event_id = state
         .nostr
-        .send_message(&content)
+        .send_message(&content, content_warning)
         .await
         .map_err(|e| format!("送信エラー: {}", e))?;
 
     Ok(event_id.to_hex())
 }
 
+/// リポスト
+#[tauri::command]
+async fn repost(event_id: String, state: State<'_, AppState>) -> Result<String, String> {
+    let repost_id = state
+        .nostr
+        .repost(&event_id)
+        .await
+        .map_err(|e| format!("リポストエラー: {}", e))?;
+
+    Ok(repost_id.to_hex())
+}
+
+/// 自分のメッセージを削除
+#[tauri::command]
+async fn delete_message(
+    event_id: String,
+    reason: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let deletion_id = state
+        .nostr
+        .delete_message(&event_id, reason)
+        .await
+        .map_err(|e| format!("削除エラー: {}", e))?;
+
+    Ok(deletion_id.to_hex())
+}
+
+/// DMを送信
+#[tauri::command]
+async fn send_dm(recipient: String, content: String, state: State<'_, AppState>) -> Result<String, String> {
+    let event_id = state
+        .nostr
+        .send_dm(&recipient, &content)
+        .await
+        .map_err(|e| format!("DM送信エラー: {}", e))?;
+
+    Ok(event_id.to_hex())
+}
+
+/// DMの会話一覧を取得
+#[tauri::command]
+async fn get_conversations(state: State<'_, AppState>) -> Result<Vec<Conversation>, String> {
+    Ok(state.nostr.get_conversations().await)
+}
+
+/// DMの履歴を取得
+#[tauri::command]
+async fn get_dm_history(peer: String, state: State<'_, AppState>) -> Result<Vec<DirectMessage>, String> {
+    state
+        .nostr
+        .get_dm_history(&peer)
+        .await
+        .map_err(|e| format!("DM履歴エラー: {}", e))
+}
+
 /// メッセージ一覧を取得（現状はダミー）
 #[tauri::command]
 async fn get_messages(state: State<'_, AppState>) -> Result<Vec<Message>, String> {
@@ -107,6 +261,16 @@ async fn get_public_key(state: State<'_, AppState>) -> Result<String, String> {
         .ok_or_else(|| "公開鍵が見つかりません".to_string())
 }
 
+/// 公開鍵を取得（hex形式）
+#[tauri::command]
+async fn get_public_key_hex(state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .nostr
+        .get_public_key_hex()
+        .await
+        .ok_or_else(|| "公開鍵が見つかりません".to_string())
+}
+
 /// 秘密鍵を取得（エクスポート用）
 #[tauri::command]
 async fn export_secret_key(state: State<'_, AppState>) -> Result<String, String> {
@@ -117,16 +281,163 @@ async fn export_secret_key(state: State<'_, AppState>) -> Result<String, String>
         .ok_or_else(|| "秘密鍵が見つかりません".to_string())
 }
 
-/// 秘密鍵をインポート
+/// 暗号化された秘密鍵を取得（エクスポート用、ncryptsec形式）
+#[tauri::command]
+async fn export_encrypted_secret_key(state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .nostr
+        .get_encrypted_secret_key()
+        .await
+        .ok_or_else(|| "パスフレーズが設定されていません".to_string())
+}
+
+/// バックアップ用の単語列を取得（一度だけ表示できる）
+#[tauri::command]
+async fn show_mnemonic(state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .nostr
+        .take_mnemonic()
+        .await
+        .map_err(|e| format!("単語の表示エラー: {}", e))
+}
+
+/// 秘密鍵をインポート（nsec / hex / 12・24語）
 #[tauri::command]
-async fn import_secret_key(key: String, state: State<'_, AppState>) -> Result<String, String> {
+async fn import_secret_key(
+    key: String,
+    passphrase: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
     state
         .nostr
-        .import_key(&key)
+        .import_key(&key, passphrase.as_deref())
         .await
         .map_err(|e| format!("インポートエラー: {}", e))
 }
 
+/// リモート署名（NIP-46）に接続（bunker:// または nostrconnect://）
+#[tauri::command]
+async fn connect_bunker(uri: String, state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .nostr
+        .connect_bunker(&uri)
+        .await
+        .map_err(|e| format!("リモート署名の接続エラー: {}", e))
+}
+
+/// 署名サーバーに渡す nostrconnect:// URI を発行
+#[tauri::command]
+async fn create_nostrconnect_uri(state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .nostr
+        .create_nostrconnect_uri()
+        .await
+        .map_err(|e| format!("URI発行エラー: {}", e))
+}
+
+/// 鍵の保護状態を取得
+#[tauri::command]
+async fn get_key_status(state: State<'_, AppState>) -> Result<KeyStatus, String> {
+    Ok(state.nostr.get_key_status().await)
+}
+
+/// パスフレーズで鍵のロックを解除（connect の前に呼ぶ）
+#[tauri::command]
+async fn unlock_keys(passphrase: String, state: State<'_, AppState>) -> Result<String, String> {
+    state
+        .nostr
+        .unlock(&passphrase)
+        .await
+        .map_err(|e| format!("ロック解除エラー: {}", e))
+}
+
+/// パスフレーズを設定
+#[tauri::command]
+async fn set_passphrase(passphrase: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .set_passphrase(&passphrase)
+        .await
+        .map_err(|e| format!("パスフレーズ設定エラー: {}", e))
+}
+
+/// パスフレーズを変更
+#[tauri::command]
+async fn change_passphrase(
+    old_passphrase: String,
+    new_passphrase: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .nostr
+        .change_passphrase(&old_passphrase, &new_passphrase)
+        .await
+        .map_err(|e| format!("パスフレーズ変更エラー: {}", e))
+}
+
+/// パスフレーズを解除
+#[tauri::command]
+async fn remove_passphrase(passphrase: String, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .remove_passphrase(&passphrase)
+        .await
+        .map_err(|e| format!("パスフレーズ解除エラー: {}", e))
+}
+
+/// アイデンティティ一覧を取得
+#[tauri::command]
+async fn list_identities(state: State<'_, AppState>) -> Result<Vec<IdentityInfo>, String> {
+    Ok(state.nostr.list_identities().await)
+}
+
+/// アイデンティティを追加（key を省略すると新しい鍵を生成）
+#[tauri::command]
+async fn add_identity(
+    name: String,
+    key: Option<String>,
+    passphrase: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<IdentityInfo, String> {
+    let identity = state
+        .nostr
+        .add_identity(&name, key.as_deref(), passphrase.as_deref())
+        .await
+        .map_err(|e| format!("アイデンティティ追加エラー: {}", e))?;
+    refresh_tray_menu(&app, &state.nostr).await;
+    Ok(identity)
+}
+
+/// アイデンティティを切り替え
+#[tauri::command]
+async fn switch_identity(
+    id: String,
+    passphrase: Option<String>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let pubkey = state
+        .nostr
+        .switch_identity(&id, passphrase.as_deref())
+        .await
+        .map_err(|e| format!("切り替えエラー: {}", e))?;
+    refresh_tray_menu(&app, &state.nostr).await;
+    Ok(pubkey)
+}
+
+/// アイデンティティを削除
+#[tauri::command]
+async fn remove_identity(id: String, app: tauri::AppHandle, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .remove_identity(&id)
+        .await
+        .map_err(|e| format!("アイデンティティ削除エラー: {}", e))?;
+    refresh_tray_menu(&app, &state.nostr).await;
+    Ok(())
+}
+
 /// ユーザーをミュート
 #[tauri::command]
 async fn mute_user(pubkey: String, state: State<'_, AppState>) -> Result<(), String> {
@@ -161,6 +472,7 @@ async fn get_my_profile(state: State<'_, AppState>) -> Result<Option<nostr_clien
 
 /// プロフィールを更新
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 async fn update_profile(
     name: Option<String>,
     display_name: Option<String>,
@@ -168,31 +480,155 @@ async fn update_profile(
     picture: Option<String>,
     website: Option<String>,
     nip05: Option<String>,
+    clear: Option<Vec<String>>,
+    new_profile: Option<bool>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     state
         .nostr
-        .update_profile(name, display_name, about, picture, website, nip05)
+        .update_profile(
+            name,
+            display_name,
+            about,
+            picture,
+            website,
+            nip05,
+            clear.unwrap_or_default(),
+            new_profile.unwrap_or(false),
+        )
         .await
         .map_err(|e| format!("プロフィール更新エラー: {}", e))
 }
 
+/// ユーザーのプロフィールを取得（kind:0 の全フィールド）
+#[tauri::command]
+async fn get_profile(pubkey: String, state: State<'_, AppState>) -> Result<Option<nostr_client::Profile>, String> {
+    state
+        .nostr
+        .get_profile(&pubkey)
+        .await
+        .map_err(|e| format!("プロフィール取得エラー: {}", e))
+}
+
+/// NIP-05 識別子（name@domain）からユーザーを探す
+#[tauri::command]
+async fn lookup_nip05(identifier: String, state: State<'_, AppState>) -> Result<Nip05Lookup, String> {
+    state
+        .nostr
+        .lookup_nip05(&identifier)
+        .await
+        .map_err(|e| format!("NIP-05 検索エラー: {}", e))
+}
+
+/// 通知一覧を取得
+#[tauri::command]
+async fn get_notifications(state: State<'_, AppState>) -> Result<Vec<Notification>, String> {
+    Ok(state.nostr.get_notifications().await)
+}
+
+/// 未読の通知数を取得
+#[tauri::command]
+async fn get_unread_count(state: State<'_, AppState>) -> Result<usize, String> {
+    Ok(state.nostr.unread_notification_count().await)
+}
+
+/// 通知を既読にする（ids を省略すると全件）
+#[tauri::command]
+async fn mark_read(
+    ids: Option<Vec<String>>,
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<usize, String> {
+    let unread = state
+        .nostr
+        .mark_read(ids)
+        .await
+        .map_err(|e| format!("既読エラー: {}", e))?;
+    notify_unread(&app, unread);
+    Ok(unread)
+}
+
+/// ユーザー設定を取得
+#[tauri::command]
+async fn get_settings(state: State<'_, AppState>) -> Result<AppSettings, String> {
+    Ok(state.nostr.get_settings().await)
+}
+
+/// コンテンツ警告の扱いを変更
+#[tauri::command]
+async fn set_content_warning_mode(
+    mode: ContentWarningMode,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    state
+        .nostr
+        .set_content_warning_mode(mode)
+        .await
+        .map_err(|e| format!("設定保存エラー: {}", e))
+}
+
+/// bot のアカウントを非表示にするかを変更
+#[tauri::command]
+async fn set_hide_bots(hide: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .set_hide_bots(hide)
+        .await
+        .map_err(|e| format!("設定保存エラー: {}", e))
+}
+
+/// キャッシュしたプロフィールを取り直すまでの時間を変更
+#[tauri::command]
+async fn set_profile_refresh_hours(hours: u64, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .set_profile_refresh_hours(hours)
+        .await
+        .map_err(|e| format!("設定保存エラー: {}", e))
+}
+
+/// 新しい鍵を単語列（NIP-06）から生成するかを変更
+#[tauri::command]
+async fn set_mnemonic_keys(enabled: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .set_mnemonic_keys(enabled)
+        .await
+        .map_err(|e| format!("設定保存エラー: {}", e))
+}
+
 /// リレーリストを取得
 #[tauri::command]
-async fn get_relays(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+async fn get_relays(state: State<'_, AppState>) -> Result<Vec<RelayEntry>, String> {
     Ok(state.nostr.get_relays().await)
 }
 
-/// リレーを追加
+/// リレーを追加（read / write は省略時は両方、probe を false にすると接続確認をしない）
 #[tauri::command]
-async fn add_relay(url: String, state: State<'_, AppState>) -> Result<(), String> {
+async fn add_relay(
+    url: String,
+    read: Option<bool>,
+    write: Option<bool>,
+    probe: Option<bool>,
+    state: State<'_, AppState>,
+) -> Result<RelayEntry, String> {
     state
         .nostr
-        .add_relay(&url)
+        .add_relay(&url, read.unwrap_or(true), write.unwrap_or(true), probe.unwrap_or(true))
         .await
         .map_err(|e| format!("リレー追加エラー: {}", e))
 }
 
+/// 追加済みのリレーの read / write を変更
+#[tauri::command]
+async fn set_relay_markers(url: String, read: bool, write: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .set_relay_markers(&url, read, write)
+        .await
+        .map_err(|e| format!("リレー設定エラー: {}", e))
+}
+
 /// リレーを削除
 #[tauri::command]
 async fn remove_relay(url: String, state: State<'_, AppState>) -> Result<(), String> {
@@ -203,6 +639,59 @@ async fn remove_relay(url: String, state: State<'_, AppState>) -> Result<(), Str
         .map_err(|e| format!("リレー削除エラー: {}", e))
 }
 
+/// リレーの情報（NIP-11）を取得
+#[tauri::command]
+async fn get_relay_info(url: String, state: State<'_, AppState>) -> Result<RelayInfo, String> {
+    state
+        .nostr
+        .get_relay_info(&url)
+        .await
+        .map_err(|e| format!("リレー情報取得エラー: {}", e))
+}
+
+/// リレーの接続と認証（NIP-42）の状態を取得
+#[tauri::command]
+async fn get_relay_statuses(state: State<'_, AppState>) -> Result<Vec<RelayStatusReport>, String> {
+    Ok(state.nostr.get_relay_statuses().await)
+}
+
+/// リレーごとの認証要求への応じ方を変更
+#[tauri::command]
+async fn set_relay_auth_policy(url: String, policy: AuthPolicy, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .set_relay_auth_policy(&url, policy)
+        .await
+        .map_err(|e| format!("設定保存エラー: {}", e))
+}
+
+/// 確認待ちの認証要求に応じる・断る
+#[tauri::command]
+async fn respond_relay_auth(url: String, allow: bool, state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .nostr
+        .respond_relay_auth(&url, allow)
+        .await
+        .map_err(|e| format!("リレー認証エラー: {}", e))
+}
+
+/// 公開済みのリレーリスト（NIP-65）にあったリレーをローカルのリストに追加
+#[tauri::command]
+async fn merge_relay_list(state: State<'_, AppState>) -> Result<Vec<RelayEntry>, String> {
+    state
+        .nostr
+        .merge_relay_list()
+        .await
+        .map_err(|e| format!("リレーリスト追加エラー: {}", e))
+}
+
+/// 公開済みのリレーリストを取り込まない
+#[tauri::command]
+async fn dismiss_relay_list(state: State<'_, AppState>) -> Result<(), String> {
+    state.nostr.dismiss_relay_list().await;
+    Ok(())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     // アプリ状態を初期化
@@ -214,7 +703,7 @@ pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .manage(app_state)
-        .invoke_handler(tauri::generate_handler![connect, send_message, get_messages, get_public_key, export_secret_key, import_secret_key, mute_user, unmute_user, get_muted_users, get_my_profile, update_profile, get_relays, add_relay, remove_relay])
+        .invoke_handler(tauri::generate_handler![connect, send_message, repost, delete_message, send_dm, get_conversations, get_dm_history, get_messages, get_public_key, get_public_key_hex, export_secret_key, export_encrypted_secret_key, show_mnemonic, import_secret_key, connect_bunker, create_nostrconnect_uri, get_key_status, unlock_keys, set_passphrase, change_passphrase, remove_passphrase, list_identities, add_identity, switch_identity, remove_identity, mute_user, unmute_user, get_muted_users, get_my_profile, get_profile, update_profile, lookup_nip05, get_notifications, get_unread_count, mark_read, get_settings, set_content_warning_mode, set_mnemonic_keys, set_profile_refresh_hours, set_hide_bots, get_relays, add_relay, set_relay_markers, remove_relay, get_relay_info, get_relay_statuses, set_relay_auth_policy, respond_relay_auth, merge_relay_list, dismiss_relay_list])
         .setup(|app| {
             // トレイアイコンのクリックイベントを設定
             if let Some(tray) = app.tray_by_id("main") {
@@ -224,10 +713,61 @@ pub fn run() {
                         toggle_overlay(app);
                     }
                 });
+
+                // 右クリックメニューからアイデンティティを切り替え
+                let _ = tray.set_show_menu_on_left_click(false);
+                tray.on_menu_event(|app, event| {
+                    let Some(id) = event.id().as_ref().strip_prefix(IDENTITY_MENU_PREFIX) else {
+                        return;
+                    };
+                    let id = id.to_string();
+                    let app = app.clone();
+                    tauri::async_runtime::spawn(async move {
+                        let nostr = app.state::<AppState>().nostr.clone();
+                        if let Err(e) = nostr.switch_identity(&id, None).await {
+                            println!("⚠️ Failed to switch identity: {}", e);
+                            let name = nostr
+                                .list_identities()
+                                .await
+                                .into_iter()
+                                .find(|identity| identity.id == id)
+                                .map_or_else(|| id.clone(), |identity| identity.name);
+                            let _ = app.emit(
+                                "identity-switch-failed",
+                                IdentitySwitchFailed {
+                                    locked: NostrState::identity_locked(&id),
+                                    id: id.clone(),
+                                    name,
+                                    message: format!("切り替えエラー: {}", e),
+                                },
+                            );
+                            // オーバーレイが隠れていてもパスフレーズの入力やエラーが見えるようにする
+                            if let Some(window) = app.get_webview_window("main") {
+                                let _ = window.show();
+                                let _ = window.set_focus();
+                            }
+                        }
+                        refresh_tray_menu(&app, &nostr).await;
+                    });
+                });
             }
 
+            // トレイメニューの初期化
+            let app_handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let nostr = app_handle.state::<AppState>().nostr.clone();
+                refresh_tray_menu(&app_handle, &nostr).await;
+            });
+
             Ok(())
         })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // 定期保存（1分ごと）の間に取得したプロフィールを失わないよう、終了時に保存する
+            if let tauri::RunEvent::Exit = event {
+                let nostr = app.state::<AppState>().nostr.clone();
+                tauri::async_runtime::block_on(nostr.flush_profile_cache());
+            }
+        });
 }
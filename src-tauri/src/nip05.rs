@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// 確認結果を使い回す時間
+const VERIFIED_TTL: Duration = Duration::from_secs(6 * 3600);
+
+/// 取得に失敗した・見つからなかった結果を使い回す時間（短めにして早めに再確認する）
+const FAILED_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// nostr.json の取得タイムアウト
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// NIP-05 識別子を解決した結果
+#[derive(Clone, Serialize)]
+pub struct Nip05Lookup {
+    pub identifier: String,  // name@domain（小文字に正規化）
+    pub pubkey: String,      // hex形式
+    pub relays: Vec<String>, // nostr.json に書かれていればそのリレー
+}
+
+/// /.well-known/nostr.json の中身
+#[derive(Deserialize)]
+struct NostrJson {
+    #[serde(default)]
+    names: HashMap<String, String>,
+    #[serde(default)]
+    relays: HashMap<String, Vec<String>>,
+}
+
+/// キャッシュした解決結果（見つからなければ lookup が None）
+struct CachedLookup {
+    lookup: Option<Nip05Lookup>,
+    fetched_at: Instant,
+}
+
+/// NIP-05 の確認（nostr.json を取得して name → pubkey を照合し、TTL 付きでキャッシュ）
+pub struct Nip05Verifier {
+    http: reqwest::Client,
+    base_url: Option<String>, // テストではドメインの代わりにこのURLへ問い合わせる
+    cache: RwLock<HashMap<String, CachedLookup>>,
+    in_flight: RwLock<HashSet<String>>,
+}
+
+impl Default for Nip05Verifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Nip05Verifier {
+    pub fn new() -> Self {
+        Self::build(None)
+    }
+
+    /// 問い合わせ先を固定した検証器（テストでローカルのHTTPサーバーに問い合わせる）
+    #[cfg(test)]
+    pub fn with_base_url(base_url: &str) -> Self {
+        Self::build(Some(base_url.trim_end_matches('/').to_string()))
+    }
+
+    fn build(base_url: Option<String>) -> Self {
+        // NIP-05 ではリダイレクトを追ってはいけない
+        let http = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            http,
+            base_url,
+            cache: RwLock::new(HashMap::new()),
+            in_flight: RwLock::new(HashSet::new()),
+        }
+    }
+
+    /// キャッシュ済みの確認結果（未確認か期限切れなら None）
+    pub async fn cached(&self, identifier: &str, pubkey_hex: &str) -> Option<bool> {
+        let (name, domain) = split_identifier(identifier)?;
+        let key = format!("{}@{}", name, domain);
+        let cache = self.cache.read().await;
+        let cached = cache.get(&key)?;
+        if cached.fetched_at.elapsed() > ttl(cached) {
+            return None;
+        }
+        Some(cached.lookup.as_ref().is_some_and(|lookup| lookup.pubkey == pubkey_hex))
+    }
+
+    /// 識別子が pubkey を指しているか確認（キャッシュがあれば問い合わせない）
+    pub async fn verify(&self, identifier: &str, pubkey_hex: &str) -> bool {
+        if let Some(verified) = self.cached(identifier, pubkey_hex).await {
+            return verified;
+        }
+        match self.lookup(identifier).await {
+            Ok(Some(lookup)) => lookup.pubkey == pubkey_hex,
+            _ => false,
+        }
+    }
+
+    /// 問い合わせ中でなければ確認を始める（始めたら true、呼び出し側が結果を待つ）
+    pub async fn begin(&self, identifier: &str) -> bool {
+        self.in_flight.write().await.insert(identifier.to_lowercase())
+    }
+
+    /// begin で始めた確認の終了を記録
+    pub async fn finish(&self, identifier: &str) {
+        self.in_flight.write().await.remove(&identifier.to_lowercase());
+    }
+
+    /// name@domain を pubkey に解決（見つからなければ None、取得できなければエラー）
+    pub async fn lookup(&self, identifier: &str) -> Result<Option<Nip05Lookup>, Box<dyn std::error::Error + Send + Sync>> {
+        let (name, domain) = split_identifier(identifier).ok_or("Identifier must be name@domain")?;
+        let key = format!("{}@{}", name, domain);
+
+        if let Some(cached) = self.cache.read().await.get(&key) {
+            if cached.fetched_at.elapsed() <= ttl(cached) {
+                return Ok(cached.lookup.clone());
+            }
+        }
+
+        let result = self.fetch(&name, &domain).await;
+        let lookup = match &result {
+            Ok(json) => json.names.get(&name).map(|pubkey| Nip05Lookup {
+                identifier: key.clone(),
+                pubkey: pubkey.to_lowercase(),
+                relays: json.relays.get(&pubkey.to_lowercase()).cloned().unwrap_or_default(),
+            }),
+            Err(_) => None,
+        };
+        self.cache.write().await.insert(
+            key,
+            CachedLookup {
+                lookup: lookup.clone(),
+                fetched_at: Instant::now(),
+            },
+        );
+
+        result.map(|_| lookup)
+    }
+
+    /// nostr.json を取得
+    async fn fetch(&self, name: &str, domain: &str) -> Result<NostrJson, Box<dyn std::error::Error + Send + Sync>> {
+        let base = match &self.base_url {
+            Some(base_url) => base_url.clone(),
+            None => format!("https://{}", domain),
+        };
+        let url = format!("{}/.well-known/nostr.json", base);
+        let response = self
+            .http
+            .get(url)
+            .query(&[("name", name)])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json::<NostrJson>().await?)
+    }
+}
+
+/// 識別子を name と domain に分ける（domain だけなら name は "_"、どちらも小文字にする）
+fn split_identifier(identifier: &str) -> Option<(String, String)> {
+    let identifier = identifier.trim().to_lowercase();
+    let (name, domain) = match identifier.split_once('@') {
+        Some((name, domain)) => (name.to_string(), domain.to_string()),
+        None => ("_".to_string(), identifier),
+    };
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    let valid_domain = domain.contains('.') && !domain.contains('/') && !domain.contains(char::is_whitespace);
+    if valid_name && valid_domain {
+        Some((name, domain))
+    } else {
+        None
+    }
+}
+
+/// キャッシュの有効期間（見つかったものは長め、失敗は短め）
+fn ttl(cached: &CachedLookup) -> Duration {
+    if cached.lookup.is_some() {
+        VERIFIED_TTL
+    } else {
+        FAILED_TTL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const PUBKEY: &str = "b0635d6a9851d3aed0cd6c495b282167acf761729078d975fc341b22650b07b9";
+
+    /// 決まった応答を返すローカルのHTTPサーバー（受けたリクエスト行を記録する）
+    async fn serve(status: &'static str, body: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]).to_string();
+                seen.lock().unwrap().push(request.lines().next().unwrap_or_default().to_string());
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nLocation: http://127.0.0.1:1/\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        (base_url, requests)
+    }
+
+    #[test]
+    fn split_identifier_lowercases_name_and_domain() {
+        assert_eq!(
+            split_identifier(" Alice@Example.COM "),
+            Some(("alice".to_string(), "example.com".to_string()))
+        );
+        assert_eq!(
+            split_identifier("bob.smith-1_x@sub.example.com"),
+            Some(("bob.smith-1_x".to_string(), "sub.example.com".to_string()))
+        );
+    }
+
+    #[test]
+    fn split_identifier_uses_underscore_for_bare_domain() {
+        assert_eq!(
+            split_identifier("example.com"),
+            Some(("_".to_string(), "example.com".to_string()))
+        );
+    }
+
+    #[test]
+    fn split_identifier_rejects_malformed_identifiers() {
+        assert_eq!(split_identifier(""), None);
+        assert_eq!(split_identifier("@example.com"), None);
+        assert_eq!(split_identifier("al ice@example.com"), None);
+        assert_eq!(split_identifier("alice+tag@example.com"), None);
+        assert_eq!(split_identifier("alice@localhost"), None);
+        assert_eq!(split_identifier("alice@example.com/path"), None);
+    }
+
+    #[tokio::test]
+    async fn lookup_resolves_pubkey_and_relays() {
+        let (base_url, requests) = serve(
+            "200 OK",
+            r#"{"names":{"alice":"B0635D6A9851D3AED0CD6C495B282167ACF761729078D975FC341B22650B07B9"},"relays":{"b0635d6a9851d3aed0cd6c495b282167acf761729078d975fc341b22650b07b9":["wss://relay.example.com"]}}"#,
+        )
+        .await;
+        let verifier = Nip05Verifier::with_base_url(&base_url);
+
+        let lookup = verifier.lookup("Alice@Example.com").await.unwrap().unwrap();
+        assert_eq!(lookup.identifier, "alice@example.com");
+        assert_eq!(lookup.pubkey, PUBKEY);
+        assert_eq!(lookup.relays, vec!["wss://relay.example.com".to_string()]);
+        assert_eq!(
+            requests.lock().unwrap().as_slice(),
+            ["GET /.well-known/nostr.json?name=alice HTTP/1.1".to_string()]
+        );
+
+        // 2回目はキャッシュから答える
+        assert!(verifier.verify("alice@example.com", PUBKEY).await);
+        assert_eq!(verifier.cached("alice@example.com", PUBKEY).await, Some(true));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn lookup_returns_none_for_unknown_name() {
+        let (base_url, _) = serve("200 OK", r#"{"names":{"bob":"b0635d6a9851d3aed0cd6c495b282167acf761729078d975fc341b22650b07b9"}}"#).await;
+        let verifier = Nip05Verifier::with_base_url(&base_url);
+
+        assert!(verifier.lookup("alice@example.com").await.unwrap().is_none());
+        assert!(!verifier.verify("alice@example.com", PUBKEY).await);
+    }
+
+    #[tokio::test]
+    async fn lookup_fails_on_http_error() {
+        let (base_url, _) = serve("404 Not Found", "").await;
+        let verifier = Nip05Verifier::with_base_url(&base_url);
+
+        assert!(verifier.lookup("alice@example.com").await.is_err());
+        assert_eq!(verifier.cached("alice@example.com", PUBKEY).await, Some(false));
+    }
+
+    #[tokio::test]
+    async fn lookup_does_not_follow_redirects() {
+        let (base_url, _) = serve("302 Found", "").await;
+        let verifier = Nip05Verifier::with_base_url(&base_url);
+
+        assert!(verifier.lookup("alice@example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn lookup_rejects_malformed_identifier_without_request() {
+        let (base_url, requests) = serve("200 OK", "{}").await;
+        let verifier = Nip05Verifier::with_base_url(&base_url);
+
+        assert!(verifier.lookup("not an identifier").await.is_err());
+        assert!(requests.lock().unwrap().is_empty());
+    }
+}
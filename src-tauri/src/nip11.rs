@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+
+/// 取得した情報を使い回す時間
+const INFO_TTL: Duration = Duration::from_secs(3600);
+
+/// 取得に失敗した結果を使い回す時間（短めにして早めに再取得する）
+const FAILED_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// 情報ドキュメントの取得タイムアウト
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// リレーの情報ドキュメント（NIP-11）
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub struct RelayInfo {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub pubkey: Option<String>, // 管理者の pubkey hex
+    #[serde(default)]
+    pub contact: Option<String>,
+    #[serde(default)]
+    pub supported_nips: Vec<u16>,
+    #[serde(default)]
+    pub software: Option<String>,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub limitation: Option<RelayLimitation>,
+    #[serde(default)]
+    pub payments_url: Option<String>,
+}
+
+/// リレーの制限（書かれていない項目は None）
+#[derive(Clone, Serialize, Deserialize, Default)]
+pub struct RelayLimitation {
+    #[serde(default)]
+    pub max_message_length: Option<u64>, // WebSocket で送る1メッセージの最大バイト数
+    #[serde(default)]
+    pub max_subscriptions: Option<u64>,
+    #[serde(default)]
+    pub max_limit: Option<u64>,
+    #[serde(default)]
+    pub max_event_tags: Option<u64>,
+    #[serde(default)]
+    pub max_content_length: Option<u64>, // content の最大文字数
+    #[serde(default)]
+    pub min_pow_difficulty: Option<u64>,
+    #[serde(default)]
+    pub auth_required: Option<bool>,
+    #[serde(default)]
+    pub payment_required: Option<bool>,
+    #[serde(default)]
+    pub restricted_writes: Option<bool>,
+}
+
+/// キャッシュした取得結果（取得できなければ info が None）
+struct CachedInfo {
+    info: Option<RelayInfo>,
+    fetched_at: Instant,
+}
+
+/// リレー情報（NIP-11）の取得とキャッシュ
+pub struct RelayInfoCache {
+    http: reqwest::Client,
+    cache: RwLock<HashMap<String, CachedInfo>>,
+}
+
+impl Default for RelayInfoCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelayInfoCache {
+    pub fn new() -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self {
+            http,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// キャッシュ済みの情報（未取得・取得失敗・期限切れなら None）
+    pub async fn cached(&self, relay_url: &str) -> Option<RelayInfo> {
+        let cache = self.cache.read().await;
+        let cached = cache.get(relay_url)?;
+        if cached.fetched_at.elapsed() > ttl(cached) {
+            return None;
+        }
+        cached.info.clone()
+    }
+
+    /// リレーの情報を取得（キャッシュがあれば問い合わせない）
+    pub async fn get(&self, relay_url: &str) -> Result<RelayInfo, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(cached) = self.cache.read().await.get(relay_url) {
+            if cached.fetched_at.elapsed() <= ttl(cached) {
+                return cached.info.clone().ok_or_else(|| "Relay information is not available".into());
+            }
+        }
+
+        let result = self.fetch(relay_url).await;
+        self.cache.write().await.insert(
+            relay_url.to_string(),
+            CachedInfo {
+                info: result.as_ref().ok().cloned(),
+                fetched_at: Instant::now(),
+            },
+        );
+        result
+    }
+
+    /// 情報ドキュメントを取得（リレーと同じURLに Accept: application/nostr+json で HTTP GET）
+    async fn fetch(&self, relay_url: &str) -> Result<RelayInfo, Box<dyn std::error::Error + Send + Sync>> {
+        let http_url = if let Some(rest) = relay_url.strip_prefix("wss://") {
+            format!("https://{}", rest)
+        } else if let Some(rest) = relay_url.strip_prefix("ws://") {
+            format!("http://{}", rest)
+        } else {
+            return Err("Relay URL must use ws:// or wss://".into());
+        };
+        let response = self
+            .http
+            .get(http_url)
+            .header(reqwest::header::ACCEPT, "application/nostr+json")
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.json::<RelayInfo>().await?)
+    }
+}
+
+/// キャッシュの有効期間（取得できたものは長め、失敗は短め）
+fn ttl(cached: &CachedInfo) -> Duration {
+    if cached.info.is_some() {
+        INFO_TTL
+    } else {
+        FAILED_TTL
+    }
+}
@@ -1,17 +1,112 @@
+use crate::bounded::BoundedMap;
+use crate::confusable;
+use crate::content::{self, ContentSegment};
+use crate::nip05::{Nip05Lookup, Nip05Verifier};
+use crate::nip11::{RelayInfo, RelayInfoCache};
+use crate::outbox::OutboxStore;
+use crate::profiles::ProfileStore;
+use crate::relay_url::{self, RelayUrlError};
+use crate::storage;
 use directories::ProjectDirs;
+use nostr_connect::prelude::*;
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio::sync::RwLock;
 
-/// 保存する鍵データ
-#[derive(Serialize, Deserialize)]
+/// 保存する鍵データ（平文の secret_key、パスフレーズで暗号化した ncryptsec、リモート署名の bunker のいずれか）
+#[derive(Serialize, Deserialize, Default)]
 struct StoredKeys {
-    secret_key: String, // hex形式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret_key: Option<String>, // hex形式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ncryptsec: Option<String>, // NIP-49 形式
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bunker: Option<StoredBunker>, // NIP-46 リモート署名（秘密鍵はローカルに置かない）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mnemonic: Option<String>, // NIP-06 の単語列（バックアップ表示するまでの間だけ保持）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted_mnemonic: Option<String>, // 鍵をパスフレーズで保護しているときは単語列を自分の鍵で暗号化（NIP-44）して保持
+}
+
+/// リモート署名（NIP-46）の接続情報
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredBunker {
+    uri: String,            // bunker:// または nostrconnect:// URI
+    app_secret_key: String, // 署名サーバーとの通信に使うアプリ側の鍵（hex形式）
+}
+
+/// 署名方法（ローカルの鍵か、NIP-46 のリモート署名）
+enum IdentitySigner {
+    Local(Keys),
+    Remote {
+        signer: NostrConnect,
+        public_key: PublicKey,
+    },
+}
+
+impl IdentitySigner {
+    fn public_key(&self) -> PublicKey {
+        match self {
+            Self::Local(keys) => keys.public_key(),
+            Self::Remote { public_key, .. } => *public_key,
+        }
+    }
+}
+
+/// 既定のアイデンティティID（設定ディレクトリ直下のファイルをそのまま使う）
+const DEFAULT_IDENTITY: &str = "default";
+
+/// 保存するアイデンティティ情報
+#[derive(Clone, Serialize, Deserialize)]
+struct IdentityEntry {
+    id: String,
+    name: String,
+    #[serde(default)]
+    pubkey: Option<String>, // npub形式（ロック中で未確認なら None）
+}
+
+/// アイデンティティ一覧（identities.json）
+#[derive(Clone, Serialize, Deserialize)]
+struct IdentityIndex {
+    active: String,
+    identities: Vec<IdentityEntry>,
+}
+
+impl Default for IdentityIndex {
+    fn default() -> Self {
+        Self {
+            active: DEFAULT_IDENTITY.to_string(),
+            identities: vec![IdentityEntry {
+                id: DEFAULT_IDENTITY.to_string(),
+                name: "メイン".to_string(),
+                pubkey: None,
+            }],
+        }
+    }
+}
+
+/// フロントエンドに返すアイデンティティ情報
+#[derive(Clone, Serialize)]
+pub struct IdentityInfo {
+    pub id: String,
+    pub name: String,
+    pub pubkey: Option<String>,
+    pub active: bool,
+}
+
+/// 鍵の保護状態
+#[derive(Clone, Serialize)]
+pub struct KeyStatus {
+    pub encrypted: bool, // パスフレーズで保護されているか
+    pub unlocked: bool,  // 鍵がメモリ上に読み込まれているか
+    pub remote: bool,    // リモート署名（NIP-46）を使っているか
+    pub mnemonic_pending: bool, // バックアップ用の単語をまだ表示していないか
 }
 
 /// フロントエンドに送るメッセージ
@@ -24,6 +119,142 @@ pub struct NostrMessage {
     pub timestamp: i64,
     pub is_post: bool,
     pub context: Option<String>, // チャンネル名やリプライ先など
+    pub reposted_by: Option<String>, // 最後にリポストしたユーザーの表示名
+    pub repost_count: usize,         // 同じノートがリポストされた回数
+    pub content_warning: Option<String>, // NIP-36 コンテンツ警告（理由が空の場合は空文字）
+    pub segments: Vec<ContentSegment>,   // 本文をURL・言及・ハッシュタグなどに分割したもの
+    pub verified_nip05: Option<String>,  // 確認済みの NIP-05 識別子（未確認・不一致なら None）
+    pub look_alike_name: bool,           // フォロー中のユーザーや自分と見分けにくい名前を名乗っている
+    pub short_npub: String,              // 名前の横に添える短縮npub
+}
+
+/// 削除されたメッセージの通知（NIP-09）
+#[derive(Clone, Serialize)]
+pub struct DeletedMessage {
+    pub id: String,
+    pub reason: Option<String>,
+}
+
+/// 通知の種類
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    /// 自分の投稿への返信
+    Reply,
+    /// 本文やタグでの言及
+    Mention,
+}
+
+/// 通知（自分宛ての返信・メンション）
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub id: String,
+    pub pubkey: String,
+    pub author: String,
+    pub content: String,
+    pub timestamp: i64,
+    pub kind: NotificationKind,
+    pub read: bool,
+}
+
+/// 新着通知（未読数つき）
+#[derive(Clone, Serialize)]
+pub struct NotificationEvent {
+    pub notification: Notification,
+    pub unread: usize,
+}
+
+/// ダイレクトメッセージ（NIP-17、旧形式の NIP-04 は受信のみ）
+#[derive(Clone, Serialize)]
+pub struct DirectMessage {
+    pub id: String,
+    pub peer: String,   // 会話相手の pubkey hex
+    pub sender: String, // 送信者の pubkey hex
+    pub content: String,
+    pub timestamp: i64,
+    pub outgoing: bool, // 自分が送ったものか
+    pub legacy: bool,   // 旧形式（kind:4 / NIP-04）か
+}
+
+/// 会話の一覧表示用サマリー
+#[derive(Clone, Serialize)]
+pub struct Conversation {
+    pub peer: String,
+    pub peer_name: String,
+    pub last_message: String,
+    pub last_timestamp: i64,
+    pub message_count: usize,
+}
+
+/// 使用中の鍵が切り替わったことの通知
+#[derive(Clone, Serialize)]
+pub struct IdentityChanged {
+    pub pubkey: String,     // npub形式
+    pub pubkey_hex: String, // 受信したメッセージの pubkey と比べる用
+}
+
+/// フロントエンドに転送するイベント
+pub enum NostrEvent {
+    Message(NostrMessage),
+    Deleted(DeletedMessage),
+    Notification(NotificationEvent),
+    DirectMessage(DirectMessage),
+    IdentityChanged(IdentityChanged),
+    MyProfile(Profile),
+    AuthorUpdated(AuthorUpdated),
+    Nip05Verified(Nip05Verified),
+    RelayListFound(RelayListOffer),
+    RelayStatus(RelayStatusReport),
+}
+
+/// 作者の NIP-05 が後から確認できた
+#[derive(Clone, Serialize)]
+pub struct Nip05Verified {
+    pub pubkey: String, // hex形式
+    pub nip05: String,  // 確認できた識別子
+}
+
+/// 作者のプロフィールが後から届いた（表示済みメッセージの名前を差し替える）
+#[derive(Clone, Serialize)]
+pub struct AuthorUpdated {
+    pub pubkey: String,        // hex形式
+    pub author: String,        // 新しい表示名
+    pub look_alike_name: bool, // 新しい名前がフォロー中のユーザーや自分と見分けにくいか
+    pub hidden_bot: bool,      // bot を非表示にする設定で bot だと分かった（表示済みのメッセージも消す）
+}
+
+/// リレーの設定（NIP-65 の read / write マーカー付き）
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayEntry {
+    pub url: String,
+    #[serde(default = "default_true")]
+    pub read: bool, // このリレーから読む（自分宛てのイベントを受け取る）
+    #[serde(default = "default_true")]
+    pub write: bool, // このリレーに書く（自分の投稿を置く）
+}
+
+impl RelayEntry {
+    fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            read: true,
+            write: true,
+        }
+    }
+}
+
+/// relays.json の1件（以前の URL だけの形式も読めるようにする）
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredRelay {
+    Url(String),
+    Entry(RelayEntry),
+}
+
+/// 他のクライアントで公開していたリレーリスト（kind:10002）に、ローカルにないリレーがあった
+#[derive(Clone, Serialize)]
+pub struct RelayListOffer {
+    pub relays: Vec<RelayEntry>, // ローカルにないか、マーカーが異なるもの
 }
 
 /// プロフィール情報
@@ -35,14 +266,268 @@ pub struct Profile {
     pub picture: Option<String>,
     pub website: Option<String>,
     pub nip05: Option<String>,
+    #[serde(default)]
+    pub banner: Option<String>,
+    #[serde(default)]
+    pub bot: bool, // 自動投稿のアカウント
+    #[serde(default)]
+    pub created_at: u64, // kind:0 の created_at
+    #[serde(default)]
+    pub extra: serde_json::Map<String, serde_json::Value>, // 上記以外のフィールド（lud16 など）
+}
+
+/// コンテンツ警告付きメッセージの扱い
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentWarningMode {
+    /// 表示しない
+    Hide,
+    /// ぼかして表示（クリックで表示）
+    #[default]
+    Blur,
+    /// そのまま表示
+    Show,
+}
+
+/// リレーからの認証要求（NIP-42）への応じ方
+#[derive(Clone, Copy, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthPolicy {
+    /// 常に応じる
+    Always,
+    /// 要求のたびに確認する
+    #[default]
+    Ask,
+    /// 応じない
+    Never,
+}
+
+/// リレーの認証（NIP-42）の状態
+#[derive(Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayAuthState {
+    /// 要求されていない
+    NotRequired,
+    /// 応じるかユーザーの確認待ち
+    AwaitingApproval,
+    /// kind:22242 を送って結果待ち
+    Authenticating,
+    Authenticated,
+    /// 応じなかった
+    Declined,
+    Failed,
+}
+
+/// リレーの接続と認証の状態（relay-status でフロントエンドに送る）
+#[derive(Clone, Serialize)]
+pub struct RelayStatusReport {
+    pub url: String,
+    pub connection: String, // nostr-sdk のリレーの状態（Connected など）
+    pub auth: RelayAuthState,
+    pub message: Option<String>, // 認証に失敗したときのリレーからの理由
+}
+
+/// リレーごとの認証の進み具合
+struct RelayAuth {
+    state: RelayAuthState,
+    challenge: Option<String>,   // まだ応じていない要求
+    auth_event: Option<EventId>, // 送った kind:22242
+    message: Option<String>,
+}
+
+impl Default for RelayAuth {
+    fn default() -> Self {
+        Self {
+            state: RelayAuthState::NotRequired,
+            challenge: None,
+            auth_event: None,
+            message: None,
+        }
+    }
+}
+
+/// リレーURL → 認証の進み具合
+type RelayAuthTracker = Arc<RwLock<HashMap<String, RelayAuth>>>;
+
+/// ユーザー設定
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AppSettings {
+    #[serde(default)]
+    pub content_warning_mode: ContentWarningMode,
+    #[serde(default = "default_true")]
+    pub mnemonic_keys: bool, // 新しい鍵を NIP-06 の単語列から生成するか
+    #[serde(default = "default_profile_refresh_hours")]
+    pub profile_refresh_hours: u64, // キャッシュしたプロフィールを取り直すまでの時間
+    #[serde(default)]
+    pub hide_bots: bool, // bot: true のアカウントの投稿を表示しない
+    #[serde(default)]
+    pub relay_auth: HashMap<String, AuthPolicy>, // リレーURL → 認証要求への応じ方（ないリレーは確認する）
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            content_warning_mode: ContentWarningMode::default(),
+            mnemonic_keys: true,
+            profile_refresh_hours: default_profile_refresh_hours(),
+            hide_bots: false,
+            relay_auth: HashMap::new(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_profile_refresh_hours() -> u64 {
+    24
+}
+
+/// プロフィールキャッシュ（pubkey hex → Profile、profiles.json に保存）
+type ProfileCache = Arc<RwLock<ProfileStore>>;
+
+/// 自分の最新の kind:0（未知のフィールドも含めて保持し、プロフィール更新のマージ元にする）
+#[derive(Clone)]
+struct OwnMetadata {
+    created_at: u64,
+    fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// 自分の kind:0 のキャッシュ（未取得なら None）
+type OwnMetadataCache = Arc<RwLock<Option<OwnMetadata>>>;
+
+/// フロントエンドへのイベント送信先（未接続なら None）
+type EventSender = Arc<RwLock<Option<mpsc::UnboundedSender<NostrEvent>>>>;
+
+/// プロフィール取得の依頼先（未接続なら None）
+type ProfileRequests = Arc<RwLock<Option<mpsc::UnboundedSender<PublicKey>>>>;
+
+/// 作者ごとの書き込み先リレー（NIP-65）とスレッドの参加者
+type OutboxCache = Arc<RwLock<OutboxStore>>;
+
+/// アウトボックスの購読を組み直す依頼先（未接続なら None）
+type OutboxRequests = Arc<RwLock<Option<mpsc::UnboundedSender<()>>>>;
+
+/// 自分の NIP-65 リレーリスト（kind:10002）の同期状態
+#[derive(Default)]
+struct RelayListSync {
+    fetched: bool,  // 公開済みのリストを確認した（見つからなかった場合も含む）
+    deferred: bool, // 確認前かマージ待ちの間にローカルのリストを変更した
 }
 
-/// プロフィールキャッシュ（pubkey hex → Profile）
-type ProfileCache = Arc<RwLock<HashMap<String, Profile>>>;
+/// リレーリストの同期状態（確認が済むまで公開しない）
+type RelayListSyncState = Arc<RwLock<RelayListSync>>;
 
 /// ミュートリスト（pubkey hex のセット）
 type MuteList = Arc<RwLock<std::collections::HashSet<String>>>;
 
+/// 使用中のアイデンティティの保存先ディレクトリ
+type IdentityDir = Arc<RwLock<Option<PathBuf>>>;
+
+/// フォローリスト（自分の kind:3 に含まれる pubkey hex のセット）
+type FollowList = Arc<RwLock<std::collections::HashSet<String>>>;
+
+/// 後から届いたプロフィールで作者を確かめ直すのに使う状態
+#[derive(Clone)]
+struct AuthorChecks {
+    follows: FollowList,
+    public_key: Arc<RwLock<Option<PublicKey>>>,
+    nip05: Arc<Nip05Verifier>,
+    settings: Arc<RwLock<AppSettings>>,
+}
+
+/// リポスト集計（元イベントID hex → リポストしたpubkey hexのセット）
+type RepostTracker = Arc<RwLock<BoundedMap<std::collections::HashSet<String>>>>;
+
+/// リポスト集計と削除管理で覚えておくイベント数の上限（超えたら古いものから忘れる）
+const MAX_TRACKED_EVENTS: usize = 5000;
+
+/// 削除管理（NIP-09）
+struct DeletionIndex {
+    /// 表示済みイベントID hex → 作者pubkey hex
+    seen: BoundedMap<String>,
+    /// 削除要求済みイベントID hex → 削除を要求したpubkey hexのセット（作者以外の要求で作者の要求を上書きしない）
+    deleted: BoundedMap<std::collections::HashSet<String>>,
+}
+
+impl Default for DeletionIndex {
+    fn default() -> Self {
+        Self {
+            seen: BoundedMap::new(MAX_TRACKED_EVENTS),
+            deleted: BoundedMap::new(MAX_TRACKED_EVENTS),
+        }
+    }
+}
+
+type Deletions = Arc<RwLock<DeletionIndex>>;
+
+/// 通知受信箱（新しい順）
+type NotificationInbox = Arc<RwLock<Vec<Notification>>>;
+
+/// 通知の保存上限
+const MAX_NOTIFICATIONS: usize = 200;
+
+/// 通知用サブスクリプションID
+const NOTIFICATIONS_SUBSCRIPTION: &str = "notifications";
+
+/// 復号済みDM（会話相手の pubkey hex → 時系列順のメッセージ）
+/// 公開ストリームとは混ぜず、ディスクにも保存しない
+type DirectMessageStore = Arc<RwLock<HashMap<String, Vec<DirectMessage>>>>;
+
+/// 復号待ちのDMの上限（DM用の3つの購読で最大1500件届く）
+const DM_QUEUE_CAPACITY: usize = 1500;
+
+/// 復号待ちのDMの渡し先（未接続なら None）
+/// 復号（リモート署名だと署名サーバーとの往復）で受信ループを止めないよう別タスクで順に処理する
+type DirectMessageQueue = Arc<RwLock<Option<mpsc::Sender<Event>>>>;
+
+/// リモート署名中に届いた未復号のDM（DMを開いたときにまとめて復号する）
+type DeferredDirectMessages = Arc<RwLock<VecDeque<Event>>>;
+
+/// 自分のプロフィール・フォローリスト用サブスクリプションID
+const SELF_SUBSCRIPTION: &str = "self";
+
+/// DM用サブスクリプションID
+const DM_SUBSCRIPTION: &str = "dms";
+
+/// 旧形式DM（受信分・送信分）用サブスクリプションID
+const LEGACY_DM_IN_SUBSCRIPTION: &str = "legacy-dms-in";
+const LEGACY_DM_OUT_SUBSCRIPTION: &str = "legacy-dms-out";
+
+/// リモート署名の応答待ち時間（署名サーバー側での承認操作を含む）
+const NIP46_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// リポスト元イベントを取得するときのタイムアウト
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 未知の作者のプロフィールをまとめて問い合わせる間隔
+const PROFILE_BATCH_INTERVAL: Duration = Duration::from_millis(300);
+
+/// 1回の問い合わせにまとめる作者数の上限
+const PROFILE_BATCH_SIZE: usize = 100;
+
+/// プロフィールキャッシュの保存と、古いプロフィールの取り直しを行う間隔
+const PROFILE_MAINTENANCE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 作者の書き込み先リレーでの購読に使うサブスクリプションIDの接頭辞（後ろにリレーのURLを付ける）
+const OUTBOX_SUBSCRIPTION_PREFIX: &str = "outbox:";
+
+/// 作者の書き込み先として、設定済みのリレーとは別に接続するリレーの上限
+const OUTBOX_MAX_EXTRA_RELAYS: usize = 8;
+
+/// フォローやスレッド参加者の変化をまとめてから購読を組み直すまでの待ち時間
+const OUTBOX_UPDATE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// リレーを追加する前の接続確認のタイムアウト
+const RELAY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 送信する EVENT メッセージのうち本文以外の大きさの見積もり（id・pubkey・署名・タグなど、署名前に確かめるので多め）
+const EVENT_MESSAGE_OVERHEAD: usize = 512;
+
+/// 自分の kind:0 が見つからず、新しいプロフィールとして作るか確認が必要なときのエラー（フロントエンドはこの文言で判定する）
+const NO_EXISTING_PROFILE: &str = "No existing profile found. Confirm to create a new profile";
+
 /// デフォルトのリレーリスト
 const DEFAULT_RELAYS: &[&str] = &[
     "wss://relay.damus.io",
@@ -56,40 +541,92 @@ const DEFAULT_RELAYS: &[&str] = &[
 /// Nostrクライアントの状態
 pub struct NostrState {
     client: Arc<RwLock<Option<Client>>>,
-    keys: Arc<RwLock<Option<Keys>>>,
-    event_sender: Arc<RwLock<Option<mpsc::UnboundedSender<NostrMessage>>>>,
+    keys: Arc<RwLock<Option<Keys>>>, // ローカルで署名する場合のみ
+    public_key: Arc<RwLock<Option<PublicKey>>>,
+    pending_nostrconnect: Arc<RwLock<Option<Keys>>>, // nostrconnect:// URI 発行済みのアプリ側の鍵
+    event_sender: EventSender,
     profiles: ProfileCache,
     muted: MuteList,
-    relays: Arc<RwLock<Vec<String>>>,
+    relays: Arc<RwLock<Vec<RelayEntry>>>,
+    pending_relay_list: Arc<RwLock<Option<Vec<RelayEntry>>>>, // マージを提案中のリレー
+    relay_list_sync: RelayListSyncState,
+    reposts: RepostTracker,
+    deletions: Deletions,
+    settings: Arc<RwLock<AppSettings>>,
+    notifications: NotificationInbox,
+    direct_messages: DirectMessageStore,
+    follows: FollowList,
+    own_metadata: OwnMetadataCache,
+    profile_requests: ProfileRequests,
+    outbox: OutboxCache,
+    outbox_requests: OutboxRequests,
+    dm_queue: DirectMessageQueue,
+    deferred_dms: DeferredDirectMessages,
+    nip05: Arc<Nip05Verifier>,
+    relay_info: Arc<RelayInfoCache>,
+    relay_auth: RelayAuthTracker,
+    identities: Arc<RwLock<IdentityIndex>>,
+    identity_dir: IdentityDir,
 }
 
 impl NostrState {
     pub fn new() -> Self {
+        // 使用中のアイデンティティを決定
+        let identities = Self::load_identity_index().unwrap_or_default();
+        let identity_dir = Self::dir_for_identity(&identities.active);
         // ミュートリストをファイルから読み込み
-        let muted = Self::load_mute_list().unwrap_or_default();
+        let muted = Self::load_mute_list(identity_dir.as_deref()).unwrap_or_default();
         // リレーリストをファイルから読み込み
-        let relays = Self::load_relay_list().unwrap_or_else(|| {
-            DEFAULT_RELAYS.iter().map(|s| s.to_string()).collect()
-        });
+        let relays = Self::load_relay_list(identity_dir.as_deref()).unwrap_or_else(Self::default_relays);
+        // ユーザー設定をファイルから読み込み
+        let settings = Self::load_settings().unwrap_or_default();
+        // 通知受信箱をファイルから読み込み
+        let notifications = Self::load_notifications(identity_dir.as_deref()).unwrap_or_default();
 
         Self {
             client: Arc::new(RwLock::new(None)),
             keys: Arc::new(RwLock::new(None)),
+            public_key: Arc::new(RwLock::new(None)),
+            pending_nostrconnect: Arc::new(RwLock::new(None)),
             event_sender: Arc::new(RwLock::new(None)),
-            profiles: Arc::new(RwLock::new(HashMap::new())),
+            profiles: Arc::new(RwLock::new(Self::load_profiles().unwrap_or_default())),
             muted: Arc::new(RwLock::new(muted)),
             relays: Arc::new(RwLock::new(relays)),
+            pending_relay_list: Arc::new(RwLock::new(None)),
+            relay_list_sync: Arc::new(RwLock::new(RelayListSync::default())),
+            reposts: Arc::new(RwLock::new(BoundedMap::new(MAX_TRACKED_EVENTS))),
+            deletions: Arc::new(RwLock::new(DeletionIndex::default())),
+            settings: Arc::new(RwLock::new(settings)),
+            notifications: Arc::new(RwLock::new(notifications)),
+            direct_messages: Arc::new(RwLock::new(HashMap::new())),
+            follows: Arc::new(RwLock::new(std::collections::HashSet::new())),
+            own_metadata: Arc::new(RwLock::new(None)),
+            profile_requests: Arc::new(RwLock::new(None)),
+            outbox: Arc::new(RwLock::new(OutboxStore::default())),
+            outbox_requests: Arc::new(RwLock::new(None)),
+            dm_queue: Arc::new(RwLock::new(None)),
+            deferred_dms: Arc::new(RwLock::new(VecDeque::new())),
+            nip05: Arc::new(Nip05Verifier::new()),
+            relay_info: Arc::new(RelayInfoCache::new()),
+            relay_auth: Arc::new(RwLock::new(HashMap::new())),
+            identities: Arc::new(RwLock::new(identities)),
+            identity_dir: Arc::new(RwLock::new(identity_dir)),
         }
     }
 
-    /// リレーリストファイルのパス
-    fn relay_list_path() -> Option<PathBuf> {
-        Self::config_dir().map(|dir| dir.join("relays.json"))
+    /// デフォルトのリレーリスト
+    fn default_relays() -> Vec<RelayEntry> {
+        DEFAULT_RELAYS.iter().map(|url| RelayEntry::new(url)).collect()
+    }
+
+    /// アイデンティティ一覧ファイルのパス（全アイデンティティ共通）
+    fn identity_index_path() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("identities.json"))
     }
 
-    /// リレーリストを読み込み
-    fn load_relay_list() -> Option<Vec<String>> {
-        let path = Self::relay_list_path()?;
+    /// アイデンティティ一覧を読み込み
+    fn load_identity_index() -> Option<IdentityIndex> {
+        let path = Self::identity_index_path()?;
         if path.exists() {
             let data = fs::read_to_string(&path).ok()?;
             serde_json::from_str(&data).ok()
@@ -98,101 +635,771 @@ impl NostrState {
         }
     }
 
-    /// リレーリストを保存
-    async fn save_relay_list(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if let Some(path) = Self::relay_list_path() {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            let relays = self.relays.read().await;
-            let data = serde_json::to_string_pretty(&*relays)?;
-            fs::write(&path, data)?;
+    /// アイデンティティ一覧を保存
+    async fn save_identity_index(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(path) = Self::identity_index_path() {
+            let identities = self.identities.read().await;
+            let data = serde_json::to_string_pretty(&*identities)?;
+            storage::write_atomic(&path, data.as_bytes())?;
         }
         Ok(())
     }
 
-    /// リレーを追加
-    pub async fn add_relay(&self, url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// アイデンティティごとの保存先（既定のものは設定ディレクトリ直下）
+    fn dir_for_identity(id: &str) -> Option<PathBuf> {
+        let config_dir = Self::config_dir()?;
+        if id == DEFAULT_IDENTITY {
+            Some(config_dir)
+        } else {
+            Some(config_dir.join("identities").join(id))
+        }
+    }
+
+    /// 使用中のアイデンティティの保存先
+    async fn identity_dir(&self) -> Option<PathBuf> {
+        self.identity_dir.read().await.clone()
+    }
+
+    /// 使用中のアイデンティティに公開鍵を記録
+    async fn remember_identity_pubkey(
+        &self,
+        public_key: &PublicKey,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let pubkey = public_key.to_bech32()?;
         {
-            let mut relays = self.relays.write().await;
-            if !relays.contains(&url.to_string()) {
-                relays.push(url.to_string());
+            let mut identities = self.identities.write().await;
+            let active = identities.active.clone();
+            match identities.identities.iter_mut().find(|entry| entry.id == active) {
+                Some(entry) if entry.pubkey.as_deref() == Some(pubkey.as_str()) => return Ok(()),
+                Some(entry) => entry.pubkey = Some(pubkey),
+                None => return Ok(()),
             }
         }
-        self.save_relay_list().await?;
+        self.save_identity_index().await
+    }
+
+    /// アイデンティティ一覧を取得
+    pub async fn list_identities(&self) -> Vec<IdentityInfo> {
+        let identities = self.identities.read().await;
+        identities
+            .identities
+            .iter()
+            .map(|entry| IdentityInfo {
+                id: entry.id.clone(),
+                name: entry.name.clone(),
+                pubkey: entry.pubkey.clone(),
+                active: entry.id == identities.active,
+            })
+            .collect()
+    }
+
+    /// アイデンティティを追加（key を省略すると新規生成、passphrase を指定すると暗号化して保存）
+    pub async fn add_identity(
+        &self,
+        name: &str,
+        key: Option<&str>,
+        passphrase: Option<&str>,
+    ) -> Result<IdentityInfo, Box<dyn std::error::Error + Send + Sync>> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("Identity name must not be empty".into());
+        }
+        let (keys, mnemonic) = match key {
+            Some(key_str) => (Keys::new(Self::parse_secret_key(key_str)?), None),
+            None => Self::generate_keys(self.settings.read().await.mnemonic_keys)?,
+        };
+        let pubkey = keys.public_key().to_bech32()?;
+        let id = keys.public_key().to_hex()[..16].to_string();
+
+        if self
+            .identities
+            .read()
+            .await
+            .identities
+            .iter()
+            .any(|entry| entry.id == id || entry.pubkey.as_deref() == Some(pubkey.as_str()))
+        {
+            return Err("This identity already exists".into());
+        }
+
+        let dir = Self::dir_for_identity(&id).ok_or("Config directory not found")?;
+        let passphrase = passphrase.filter(|p| !p.is_empty());
+        let stored = Self::to_stored_keys_with_mnemonic(&keys, passphrase, mnemonic)?;
+        Self::write_stored_keys(Some(&dir), &stored, passphrase)?;
+
+        self.identities.write().await.identities.push(IdentityEntry {
+            id: id.clone(),
+            name: name.to_string(),
+            pubkey: Some(pubkey.clone()),
+        });
+        self.save_identity_index().await?;
+
+        Ok(IdentityInfo {
+            id,
+            name: name.to_string(),
+            pubkey: Some(pubkey),
+            active: false,
+        })
+    }
+
+    /// アイデンティティを切り替え（鍵・ミュート・リレー・通知をまとめて差し替える）
+    pub async fn switch_identity(
+        &self,
+        id: &str,
+        passphrase: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.identities.read().await.identities.iter().any(|entry| entry.id == id) {
+            return Err("Identity not found".into());
+        }
+        if self.identities.read().await.active == id {
+            return self.get_public_key().await.ok_or_else(|| "Keys not initialized".into());
+        }
 
-        // 接続中のクライアントにも追加
+        // 鍵を読み込み（パスフレーズ保護されていれば復号、リモート署名なら接続）
+        let dir = Self::dir_for_identity(id).ok_or("Config directory not found")?;
+        if Self::read_stored_keys(Some(&dir))?.is_none() {
+            return Err("Key file not found".into());
+        }
+        let use_mnemonic = self.settings.read().await.mnemonic_keys;
+        let signer = Self::load_signer(Some(&dir), passphrase, use_mnemonic).await?;
+
+        // アイデンティティごとの設定を読み込み
+        let old_relays = self.relays.read().await.clone();
+        let new_relays = Self::load_relay_list(Some(&dir)).unwrap_or_else(Self::default_relays);
+        *self.muted.write().await = Self::load_mute_list(Some(&dir)).unwrap_or_default();
+        *self.relays.write().await = new_relays.clone();
+        *self.notifications.write().await = Self::load_notifications(Some(&dir)).unwrap_or_default();
+        *self.identity_dir.write().await = Some(dir);
+
+        self.identities.write().await.active = id.to_string();
+        self.save_identity_index().await?;
+
+        // 接続中ならリレーを入れ替え
+        *self.pending_relay_list.write().await = None;
         if let Some(client) = self.client.read().await.as_ref() {
-            let _ = client.add_relay(url).await;
+            for relay in old_relays.iter().filter(|r| !new_relays.contains(r)) {
+                let _ = client.remove_relay(relay.url.as_str()).await;
+            }
+            for relay in new_relays.iter().filter(|r| !old_relays.contains(r)) {
+                let _ = Self::add_client_relay(client, relay).await;
+            }
             client.connect().await;
         }
-        Ok(())
+
+        let public_key = signer.public_key();
+        self.remember_identity_pubkey(&public_key).await?;
+        self.switch_signer(signer).await?;
+        Ok(public_key.to_bech32()?)
     }
 
-    /// リレーを削除
-    pub async fn remove_relay(&self, url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        {
-            let mut relays = self.relays.write().await;
-            relays.retain(|r| r != url);
+    /// アイデンティティを削除（使用中と既定のものは削除できない）
+    pub async fn remove_identity(&self, id: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if id == DEFAULT_IDENTITY {
+            return Err("The default identity cannot be removed".into());
+        }
+        if self.identities.read().await.active == id {
+            return Err("Switch to another identity before removing this one".into());
         }
-        self.save_relay_list().await?;
 
-        // 接続中のクライアントからも削除
-        if let Some(client) = self.client.read().await.as_ref() {
-            let _ = client.remove_relay(url).await;
+        if !self.identities.read().await.identities.iter().any(|entry| entry.id == id) {
+            return Err("Identity not found".into());
+        }
+
+        // 鍵を失わないよう、ディレクトリは消さずに trash/ へ移す（移せなければ一覧からも外さない）
+        if let Some(dir) = Self::dir_for_identity(id) {
+            if dir.exists() {
+                let trash_dir = Self::config_dir().ok_or("Config directory not found")?.join("trash");
+                fs::create_dir_all(&trash_dir)?;
+                let removed_at = chrono::Local::now().format("%Y%m%d-%H%M%S");
+                fs::rename(&dir, trash_dir.join(format!("{}-{}", id, removed_at)))?;
+            }
         }
+
+        self.identities.write().await.identities.retain(|entry| entry.id != id);
+        self.save_identity_index().await?;
         Ok(())
     }
 
-    /// リレーリストを取得
-    pub async fn get_relays(&self) -> Vec<String> {
-        self.relays.read().await.clone()
+    /// アイデンティティの鍵がパスフレーズで保護されているか（切り替えにパスフレーズが要るか）
+    pub fn identity_locked(id: &str) -> bool {
+        Self::read_stored_keys(Self::dir_for_identity(id).as_deref())
+            .ok()
+            .flatten()
+            .is_some_and(|stored| stored.ncryptsec.is_some())
     }
 
-    /// ミュートリストファイルのパス
-    fn mute_list_path() -> Option<PathBuf> {
-        Self::config_dir().map(|dir| dir.join("muted.json"))
+    /// リレーリストファイルのパス
+    fn relay_list_path(dir: Option<&Path>) -> Option<PathBuf> {
+        dir.map(|dir| dir.join("relays.json"))
     }
 
-    /// ミュートリストを読み込み
-    fn load_mute_list() -> Option<std::collections::HashSet<String>> {
-        let path = Self::mute_list_path()?;
+    /// リレーリストを読み込み（URLを正規化し、読めないものと重複は除く）
+    fn load_relay_list(dir: Option<&Path>) -> Option<Vec<RelayEntry>> {
+        let path = Self::relay_list_path(dir)?;
         if path.exists() {
             let data = fs::read_to_string(&path).ok()?;
-            serde_json::from_str(&data).ok()
+            let stored: Vec<StoredRelay> = serde_json::from_str(&data).ok()?;
+            let mut relays: Vec<RelayEntry> = Vec::new();
+            for relay in stored {
+                let mut entry = match relay {
+                    StoredRelay::Url(url) => RelayEntry::new(&url),
+                    StoredRelay::Entry(entry) => entry,
+                };
+                let Ok(url) = relay_url::normalize(&entry.url) else {
+                    println!("⚠️ Skipping invalid relay URL: {}", entry.url);
+                    continue;
+                };
+                entry.url = url;
+                if !relays.iter().any(|relay| relay.url == entry.url) {
+                    relays.push(entry);
+                }
+            }
+            Some(relays)
         } else {
             None
         }
     }
 
-    /// ミュートリストを保存
-    async fn save_mute_list(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        if let Some(path) = Self::mute_list_path() {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            let muted = self.muted.read().await;
-            let data = serde_json::to_string_pretty(&*muted)?;
-            fs::write(&path, data)?;
+    /// リレーリストを保存
+    async fn save_relay_list(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = self.identity_dir().await;
+        if let Some(path) = Self::relay_list_path(dir.as_deref()) {
+            let relays = self.relays.read().await;
+            let data = serde_json::to_string_pretty(&*relays)?;
+            storage::write_atomic(&path, data.as_bytes())?;
         }
         Ok(())
     }
 
-    /// ユーザーをミュート
-    pub async fn mute_user(&self, pubkey: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.muted.write().await.insert(pubkey.to_string());
-        self.save_mute_list().await
+    /// リレーを追加して NIP-65 のリレーリストを公開（URLは正規化して返す）
+    /// probe を指定すると、接続できることを確かめてから保存する
+    pub async fn add_relay(
+        &self,
+        url: &str,
+        read: bool,
+        write: bool,
+        probe: bool,
+    ) -> Result<RelayEntry, Box<dyn std::error::Error + Send + Sync>> {
+        if !read && !write {
+            return Err("A relay must be used for reading, writing or both".into());
+        }
+        let url = relay_url::normalize(url)?;
+        if self.relays.read().await.iter().any(|relay| relay.url == url) {
+            return Err(RelayUrlError::Duplicate(url).into());
+        }
+        if probe {
+            Self::probe_relay(&url).await?;
+        }
+        let entry = RelayEntry { url, read, write };
+
+        // 接続中のクライアントに追加できなければ保存しない
+        if let Some(client) = self.client.read().await.as_ref() {
+            Self::add_client_relay(client, &entry).await?;
+            client.connect().await;
+        }
+        {
+            let mut relays = self.relays.write().await;
+            if relays.iter().any(|relay| relay.url == entry.url) {
+                return Err(RelayUrlError::Duplicate(entry.url).into());
+            }
+            relays.push(entry.clone());
+        }
+        self.save_relay_list().await?;
+        self.publish_relay_list().await;
+        self.prefetch_relay_info(vec![entry.url.clone()]);
+        Ok(entry)
     }
 
-    /// ユーザーのミュートを解除
-    pub async fn unmute_user(&self, pubkey: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.muted.write().await.remove(pubkey);
-        self.save_mute_list().await
+    /// 追加済みのリレーの read / write を変更して NIP-65 のリレーリストを公開
+    pub async fn set_relay_markers(
+        &self,
+        url: &str,
+        read: bool,
+        write: bool,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if !read && !write {
+            return Err("A relay must be used for reading, writing or both".into());
+        }
+        let url = relay_url::normalize(url)?;
+        let entry = RelayEntry { url, read, write };
+        {
+            let mut relays = self.relays.write().await;
+            let existing = relays
+                .iter_mut()
+                .find(|relay| relay.url == entry.url)
+                .ok_or("Relay not found")?;
+            if *existing == entry {
+                return Ok(());
+            }
+            *existing = entry.clone();
+        }
+        self.save_relay_list().await?;
+
+        // 接続中のクライアントにはマーカーを変えて入れ直す
+        if let Some(client) = self.client.read().await.as_ref() {
+            let _ = client.remove_relay(entry.url.as_str()).await;
+            Self::add_client_relay(client, &entry).await?;
+            client.connect().await;
+        }
+        self.publish_relay_list().await;
+        Ok(())
     }
 
-    /// ミュートリストを取得
-    pub async fn get_muted_users(&self) -> Vec<String> {
-        self.muted.read().await.iter().cloned().collect()
+    /// リレーの情報（NIP-11）を取得（1時間キャッシュ）
+    pub async fn get_relay_info(&self, url: &str) -> Result<RelayInfo, Box<dyn std::error::Error + Send + Sync>> {
+        let url = relay_url::normalize(url)?;
+        self.relay_info.get(&url).await
+    }
+
+    /// リレーの情報を裏で取得しておく（送信前の制限の確認に使う）
+    fn prefetch_relay_info(&self, urls: Vec<String>) {
+        let relay_info = self.relay_info.clone();
+        tokio::spawn(async move {
+            for url in urls {
+                if let Err(e) = relay_info.get(&url).await {
+                    println!("⚠️ Relay information unavailable for {}: {}", url, e);
+                }
+            }
+        });
+    }
+
+    /// 書き込み先リレーの既知の制限（NIP-11）を超えていないか確認（情報を取得できていないリレーは確かめない）
+    async fn check_relay_limits(
+        &self,
+        content: &str,
+        tag_count: usize,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let write_relays: Vec<String> = self
+            .relays
+            .read()
+            .await
+            .iter()
+            .filter(|relay| relay.write)
+            .map(|relay| relay.url.clone())
+            .collect();
+        let content_length = content.chars().count() as u64;
+        let message_length = (serde_json::to_string(content)?.len() + EVENT_MESSAGE_OVERHEAD) as u64;
+        for relay in write_relays {
+            let Some(limitation) = self.relay_info.cached(&relay).await.and_then(|info| info.limitation) else {
+                continue;
+            };
+            if let Some(max) = limitation.max_content_length {
+                if content_length > max {
+                    return Err(format!("Message is too long for {} ({} / {} characters)", relay, content_length, max).into());
+                }
+            }
+            if let Some(max) = limitation.max_message_length {
+                if message_length > max {
+                    return Err(format!("Message is too large for {} (about {} / {} bytes)", relay, message_length, max).into());
+                }
+            }
+            if let Some(max) = limitation.max_event_tags {
+                if tag_count as u64 > max {
+                    return Err(format!("Too many tags for {} ({} / {})", relay, tag_count, max).into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// リレーに接続できるか確かめる（確認用の接続はすぐ閉じる）
+    async fn probe_relay(url: &str) -> Result<(), RelayUrlError> {
+        let relay_url = RelayUrl::parse(url).map_err(|e| RelayUrlError::Invalid(e.to_string()))?;
+        let relay = Relay::new(relay_url);
+        let result = relay.try_connect(RELAY_PROBE_TIMEOUT).await;
+        let _ = relay.disconnect();
+        result.map_err(|e| RelayUrlError::Unreachable(url.to_string(), e.to_string()))
+    }
+
+    /// リレーを削除して NIP-65 のリレーリストを公開
+    pub async fn remove_relay(&self, url: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // 正規化前の形式で保存されていたものも消せるよう、読めないURLはそのまま使う
+        let url = relay_url::normalize(url).unwrap_or_else(|_| url.to_string());
+        let url = url.as_str();
+        {
+            let mut relays = self.relays.write().await;
+            relays.retain(|r| r.url != url);
+        }
+        self.save_relay_list().await?;
+
+        // 接続中のクライアントからも削除
+        if let Some(client) = self.client.read().await.as_ref() {
+            let _ = client.remove_relay(url).await;
+        }
+        self.publish_relay_list().await;
+        Ok(())
+    }
+
+    /// リレーリストを取得
+    pub async fn get_relays(&self) -> Vec<RelayEntry> {
+        self.relays.read().await.clone()
+    }
+
+    /// read / write に合わせてクライアントにリレーを追加
+    async fn add_client_relay(client: &Client, relay: &RelayEntry) -> Result<bool, nostr_sdk::client::Error> {
+        match (relay.read, relay.write) {
+            (true, false) => client.add_read_relay(relay.url.as_str()).await,
+            (false, true) => client.add_write_relay(relay.url.as_str()).await,
+            _ => client.add_relay(relay.url.as_str()).await,
+        }
+    }
+
+    /// NIP-65 のリレーリスト（kind:10002）を公開（未接続なら何もしない、失敗してもローカルの設定は保つ）
+    /// 公開済みのリストを確認する前やマージ待ちの間は、リモートのリストを上書きしないよう後回しにする
+    async fn publish_relay_list(&self) {
+        let Some(client) = self.client.read().await.clone() else {
+            return;
+        };
+        let pending = self.pending_relay_list.read().await.is_some();
+        {
+            let mut sync = self.relay_list_sync.write().await;
+            if !sync.fetched || pending {
+                sync.deferred = true;
+                println!("📡 Relay list publish deferred until the published list is merged or dismissed");
+                return;
+            }
+            sync.deferred = false;
+        }
+        let tags: Vec<Tag> = self
+            .relays
+            .read()
+            .await
+            .iter()
+            .filter_map(|relay| {
+                let mut tag = vec!["r".to_string(), relay.url.clone()];
+                match (relay.read, relay.write) {
+                    (true, false) => tag.push("read".to_string()),
+                    (false, true) => tag.push("write".to_string()),
+                    _ => {}
+                }
+                Tag::parse(tag).ok()
+            })
+            .collect();
+        let builder = EventBuilder::new(Kind::RelayList, "").tags(tags);
+        match client.send_event_builder(builder).await {
+            Ok(_) => println!("📡 Relay list (NIP-65) published"),
+            Err(e) => println!("⚠️ Failed to publish relay list: {}", e),
+        }
+    }
+
+    /// kind:10002 の r タグをリレー設定に変換（マーカーなしは read / write 両方、URLは正規化して読めないものは除く）
+    fn relay_entries_from_event(event: &Event) -> Vec<RelayEntry> {
+        event
+            .tags
+            .iter()
+            .filter_map(|tag| {
+                let tag_vec = tag.clone().to_vec();
+                if tag_vec.len() < 2 || tag_vec[0] != "r" {
+                    return None;
+                }
+                let url = relay_url::normalize(&tag_vec[1]).ok()?;
+                let marker = tag_vec.get(2).map(String::as_str);
+                Some(RelayEntry {
+                    url,
+                    read: marker != Some("write"),
+                    write: marker != Some("read"),
+                })
+            })
+            .collect()
+    }
+
+    /// 提案中のリレーリストをローカルのリストにマージ（同じURLは read / write を合わせる）
+    pub async fn merge_relay_list(&self) -> Result<Vec<RelayEntry>, Box<dyn std::error::Error + Send + Sync>> {
+        let pending = self
+            .pending_relay_list
+            .write()
+            .await
+            .take()
+            .ok_or("No relay list to merge")?;
+        let mut added = Vec::new();
+        {
+            let mut relays = self.relays.write().await;
+            for remote in pending {
+                match relays.iter_mut().find(|relay| relay.url == remote.url) {
+                    Some(existing) => {
+                        existing.read |= remote.read;
+                        existing.write |= remote.write;
+                        added.push(existing.clone());
+                    }
+                    None => {
+                        relays.push(remote.clone());
+                        added.push(remote);
+                    }
+                }
+            }
+        }
+        self.save_relay_list().await?;
+
+        if let Some(client) = self.client.read().await.as_ref() {
+            for relay in &added {
+                let _ = client.remove_relay(relay.url.as_str()).await;
+                let _ = Self::add_client_relay(client, relay).await;
+            }
+            client.connect().await;
+        }
+        self.publish_relay_list().await;
+        Ok(self.get_relays().await)
+    }
+
+    /// 提案中のリレーリストを破棄（待たせていたローカルの変更があればここで公開）
+    pub async fn dismiss_relay_list(&self) {
+        *self.pending_relay_list.write().await = None;
+        if self.relay_list_sync.read().await.deferred {
+            self.publish_relay_list().await;
+        }
+    }
+
+    /// プロフィールキャッシュのパス（アイデンティティをまたいで共有）
+    fn profiles_path() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("profiles.json"))
+    }
+
+    /// プロフィールキャッシュを読み込み
+    fn load_profiles() -> Option<ProfileStore> {
+        let path = Self::profiles_path()?;
+        if path.exists() {
+            let data = fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&data).ok()
+        } else {
+            None
+        }
+    }
+
+    /// プロフィールキャッシュの未保存の変更を保存（終了時に呼ぶ）
+    pub async fn flush_profile_cache(&self) {
+        if let Err(e) = Self::save_profiles(&self.profiles).await {
+            println!("⚠️ Failed to save profile cache: {}", e);
+        }
+    }
+
+    /// プロフィールキャッシュを保存（変更がなければ何もしない）
+    async fn save_profiles(profiles: &ProfileCache) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Some(path) = Self::profiles_path() else {
+            return Ok(());
+        };
+        let data = {
+            let mut profiles = profiles.write().await;
+            if !profiles.take_dirty() {
+                return Ok(());
+            }
+            serde_json::to_string(&*profiles)?
+        };
+        storage::write_atomic(&path, data.as_bytes())?;
+        Ok(())
+    }
+
+    /// 設定ファイルのパス
+    fn settings_path() -> Option<PathBuf> {
+        Self::config_dir().map(|dir| dir.join("settings.json"))
+    }
+
+    /// 設定を読み込み
+    fn load_settings() -> Option<AppSettings> {
+        let path = Self::settings_path()?;
+        if path.exists() {
+            let data = fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&data).ok()
+        } else {
+            None
+        }
+    }
+
+    /// 設定を保存
+    async fn save_settings(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(path) = Self::settings_path() {
+            let settings = self.settings.read().await;
+            let data = serde_json::to_string_pretty(&*settings)?;
+            storage::write_atomic(&path, data.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// 設定を取得
+    pub async fn get_settings(&self) -> AppSettings {
+        self.settings.read().await.clone()
+    }
+
+    /// コンテンツ警告の扱いを変更
+    pub async fn set_content_warning_mode(
+        &self,
+        mode: ContentWarningMode,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.settings.write().await.content_warning_mode = mode;
+        self.save_settings().await
+    }
+
+    /// bot のアカウントを非表示にするかを変更
+    pub async fn set_hide_bots(&self, hide: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.settings.write().await.hide_bots = hide;
+        self.save_settings().await
+    }
+
+    /// キャッシュしたプロフィールを取り直すまでの時間を変更
+    pub async fn set_profile_refresh_hours(&self, hours: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if hours == 0 {
+            return Err("Refresh interval must be at least 1 hour".into());
+        }
+        self.settings.write().await.profile_refresh_hours = hours;
+        self.save_settings().await
+    }
+
+    /// 新しい鍵を単語列から生成するかを変更
+    pub async fn set_mnemonic_keys(&self, enabled: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.settings.write().await.mnemonic_keys = enabled;
+        self.save_settings().await
+    }
+
+    /// 通知受信箱ファイルのパス
+    fn notifications_path(dir: Option<&Path>) -> Option<PathBuf> {
+        dir.map(|dir| dir.join("notifications.json"))
+    }
+
+    /// 通知受信箱を読み込み
+    fn load_notifications(dir: Option<&Path>) -> Option<Vec<Notification>> {
+        let path = Self::notifications_path(dir)?;
+        if path.exists() {
+            let data = fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&data).ok()
+        } else {
+            None
+        }
+    }
+
+    /// 通知受信箱を保存
+    fn save_notifications(
+        dir: Option<&Path>,
+        items: &[Notification],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(path) = Self::notifications_path(dir) {
+            let data = serde_json::to_string_pretty(items)?;
+            storage::write_atomic(&path, data.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// 通知一覧を取得（新しい順）
+    pub async fn get_notifications(&self) -> Vec<Notification> {
+        self.notifications.read().await.clone()
+    }
+
+    /// 未読の通知数
+    pub async fn unread_notification_count(&self) -> usize {
+        self.notifications.read().await.iter().filter(|n| !n.read).count()
+    }
+
+    /// 通知を既読にする（ids が None なら全件）
+    pub async fn mark_read(
+        &self,
+        ids: Option<Vec<String>>,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let mut notifications = self.notifications.write().await;
+        for notification in notifications.iter_mut() {
+            let selected = match &ids {
+                Some(ids) => ids.contains(&notification.id),
+                None => true,
+            };
+            if selected {
+                notification.read = true;
+            }
+        }
+        Self::save_notifications(self.identity_dir().await.as_deref(), &notifications)?;
+        Ok(notifications.iter().filter(|n| !n.read).count())
+    }
+
+    /// 自分宛てのイベントなら通知として受信箱に追加（新規なら未読数を返す）
+    async fn record_notification(
+        inbox: &NotificationInbox,
+        identity_dir: &IdentityDir,
+        event: &Event,
+        my_pubkey: &PublicKey,
+        author: String,
+    ) -> Option<NotificationEvent> {
+        if event.pubkey == *my_pubkey {
+            return None;
+        }
+        let my_hex = my_pubkey.to_hex();
+        if !Self::tag_values(event, "p").contains(&my_hex) {
+            return None;
+        }
+
+        let kind = if event.kind == Kind::TextNote && !Self::tag_values(event, "e").is_empty() {
+            NotificationKind::Reply
+        } else {
+            NotificationKind::Mention
+        };
+        let notification = Notification {
+            id: event.id.to_hex(),
+            pubkey: event.pubkey.to_hex(),
+            author,
+            content: event.content.clone(),
+            timestamp: event.created_at.as_u64() as i64,
+            kind,
+            read: false,
+        };
+
+        let mut items = inbox.write().await;
+        if items.iter().any(|n| n.id == notification.id) {
+            return None;
+        }
+        let position = items
+            .iter()
+            .position(|n| n.timestamp < notification.timestamp)
+            .unwrap_or(items.len());
+        items.insert(position, notification.clone());
+        items.truncate(MAX_NOTIFICATIONS);
+        let dir = identity_dir.read().await.clone();
+        if let Err(e) = Self::save_notifications(dir.as_deref(), &items) {
+            println!("⚠️ Failed to save notifications: {}", e);
+        }
+
+        Some(NotificationEvent {
+            notification,
+            unread: items.iter().filter(|n| !n.read).count(),
+        })
+    }
+
+    /// ミュートリストファイルのパス
+    fn mute_list_path(dir: Option<&Path>) -> Option<PathBuf> {
+        dir.map(|dir| dir.join("muted.json"))
+    }
+
+    /// ミュートリストを読み込み
+    fn load_mute_list(dir: Option<&Path>) -> Option<std::collections::HashSet<String>> {
+        let path = Self::mute_list_path(dir)?;
+        if path.exists() {
+            let data = fs::read_to_string(&path).ok()?;
+            serde_json::from_str(&data).ok()
+        } else {
+            None
+        }
+    }
+
+    /// ミュートリストを保存
+    async fn save_mute_list(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = self.identity_dir().await;
+        if let Some(path) = Self::mute_list_path(dir.as_deref()) {
+            let muted = self.muted.read().await;
+            let data = serde_json::to_string_pretty(&*muted)?;
+            storage::write_atomic(&path, data.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// ユーザーをミュート
+    pub async fn mute_user(&self, pubkey: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.muted.write().await.insert(pubkey.to_string());
+        self.save_mute_list().await
+    }
+
+    /// ユーザーのミュートを解除
+    pub async fn unmute_user(&self, pubkey: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.muted.write().await.remove(pubkey);
+        self.save_mute_list().await
+    }
+
+    /// ミュートリストを取得
+    pub async fn get_muted_users(&self) -> Vec<String> {
+        self.muted.read().await.iter().cloned().collect()
     }
 
     /// ユーザーがミュートされているか確認（将来のワードミュートやUI側での確認用）
@@ -284,7 +1491,7 @@ impl NostrState {
     }
 
     /// pubkeyから表示名を取得（短縮形式）
-    fn format_author(pubkey: &PublicKey, profiles: &HashMap<String, Profile>) -> String {
+    fn format_author(pubkey: &PublicKey, profiles: &ProfileStore) -> String {
         let hex = pubkey.to_hex();
 
         // キャッシュにプロフィールがあれば使用
@@ -302,7 +1509,12 @@ impl NostrState {
         }
 
         // プロフィールがなければ短縮npub
-        let npub = pubkey.to_bech32().unwrap_or_else(|_| hex.clone());
+        Self::short_npub(pubkey)
+    }
+
+    /// 短縮npub（npub1abc...wxyz）
+    fn short_npub(pubkey: &PublicKey) -> String {
+        let npub = pubkey.to_bech32().unwrap_or_else(|_| pubkey.to_hex());
         if npub.len() > 12 {
             format!("{}...{}", &npub[..8], &npub[npub.len()-4..])
         } else {
@@ -310,215 +1522,2000 @@ impl NostrState {
         }
     }
 
-    /// イベント受信チャンネルを設定
-    pub async fn set_event_sender(&self, sender: mpsc::UnboundedSender<NostrMessage>) {
-        *self.event_sender.write().await = Some(sender);
+    /// 作者の表示名が、フォロー中のユーザーや自分の名前と見分けにくいか
+    /// 自分とフォロー中のユーザーは本人とみなして対象外
+    fn is_look_alike_name(
+        pubkey: &PublicKey,
+        profiles: &ProfileStore,
+        follows: &std::collections::HashSet<String>,
+        my_pubkey: &PublicKey,
+    ) -> bool {
+        let hex = pubkey.to_hex();
+        if pubkey == my_pubkey || follows.contains(&hex) {
+            return false;
+        }
+        let Some(profile) = profiles.get(&hex) else {
+            return false;
+        };
+        let Some(name) = [&profile.display_name, &profile.name]
+            .into_iter()
+            .flatten()
+            .find(|name| !name.is_empty())
+        else {
+            return false;
+        };
+
+        let my_hex = my_pubkey.to_hex();
+        std::iter::once(&my_hex)
+            .chain(follows.iter())
+            .filter_map(|other| profiles.get(other))
+            .any(|other| {
+                [&other.display_name, &other.name]
+                    .into_iter()
+                    .flatten()
+                    .any(|other_name| confusable::looks_alike(name, other_name))
+            })
     }
 
-    /// 設定ディレクトリのパスを取得
-    fn config_dir() -> Option<PathBuf> {
-        ProjectDirs::from("com", "gilga", "Gilga").map(|dirs| dirs.config_dir().to_path_buf())
+    /// イベントからフロントエンド向けメッセージを組み立て
+    fn build_message(event: &Event, profiles: &ProfileStore) -> NostrMessage {
+        let emojis = content::emoji_tags(event);
+        let segments = content::parse(&event.content, &emojis, |pubkey| {
+            Self::format_author(pubkey, profiles)
+        });
+
+        NostrMessage {
+            id: event.id.to_hex(),
+            pubkey: event.pubkey.to_hex(),
+            author: Self::format_author(&event.pubkey, profiles),
+            content: event.content.clone(),
+            timestamp: event.created_at.as_u64() as i64,
+            is_post: event.kind == Kind::TextNote,
+            context: Self::extract_context(event),
+            reposted_by: None,
+            repost_count: 0,
+            content_warning: Self::extract_content_warning(event),
+            segments,
+            verified_nip05: None,
+            look_alike_name: false,
+            short_npub: Self::short_npub(&event.pubkey),
+        }
     }
 
-    /// 鍵ファイルのパスを取得
-    fn keys_path() -> Option<PathBuf> {
-        Self::config_dir().map(|dir| dir.join("keys.json"))
+    /// コンテンツ警告（NIP-36）を抽出
+    fn extract_content_warning(event: &Event) -> Option<String> {
+        event.tags.iter().find_map(|tag| {
+            let tag_vec = tag.clone().to_vec();
+            if tag_vec.first().map(String::as_str) == Some("content-warning") {
+                Some(tag_vec.get(1).cloned().unwrap_or_default())
+            } else {
+                None
+            }
+        })
     }
 
-    /// 保存された鍵を読み込み、なければ新規生成
-    fn load_or_generate_keys() -> Result<Keys, Box<dyn std::error::Error + Send + Sync>> {
-        if let Some(path) = Self::keys_path() {
-            if path.exists() {
-                // 既存の鍵を読み込み
-                let data = fs::read_to_string(&path)?;
-                let stored: StoredKeys = serde_json::from_str(&data)?;
-                let secret_key = SecretKey::from_hex(&stored.secret_key)?;
-                let keys = Keys::new(secret_key);
-                return Ok(keys);
+    /// リポスト元のイベントを解決（埋め込みJSONがなければeタグのIDでリレーから取得）
+    async fn resolve_repost(client: &Client, repost: &Event) -> Option<Event> {
+        if !repost.content.trim().is_empty() {
+            if let Ok(embedded) = Event::from_json(&repost.content) {
+                if embedded.verify().is_ok() {
+                    return Self::displayable_repost_target(embedded);
+                }
             }
         }
 
-        // 新規生成
-        let keys = Keys::generate();
+        let target_id = Self::tag_values(repost, "e")
+            .iter()
+            .find_map(|id| EventId::from_hex(id).ok())?;
 
-        // 保存
-        if let Some(path) = Self::keys_path() {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-            let stored = StoredKeys {
-                secret_key: keys.secret_key().to_secret_hex(),
-            };
-            let data = serde_json::to_string_pretty(&stored)?;
-            fs::write(&path, data)?;
-        }
+        let filter = Filter::new().id(target_id).limit(1);
+        let events = client.fetch_events(filter, FETCH_TIMEOUT).await.ok()?;
+        let original = events.into_iter().next()?;
+        Self::displayable_repost_target(original)
+    }
 
-        Ok(keys)
+    /// 指定した名前のタグの値（2番目の要素）を列挙
+    fn tag_values(event: &Event, name: &str) -> Vec<String> {
+        event
+            .tags
+            .iter()
+            .filter_map(|tag| {
+                let tag_vec = tag.clone().to_vec();
+                if tag_vec.len() >= 2 && tag_vec[0] == name {
+                    Some(tag_vec[1].clone())
+                } else {
+                    None
+                }
+            })
+            .collect()
     }
 
-    /// 初期化（鍵読み込み/生成 + リレー接続）
-    pub async fn init(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // 鍵を読み込み or 生成
-        let keys = Self::load_or_generate_keys()?;
+    /// コンテンツ警告付きで、非表示設定になっているか
+    async fn is_hidden_by_content_warning(settings: &Arc<RwLock<AppSettings>>, event: &Event) -> bool {
+        settings.read().await.content_warning_mode == ContentWarningMode::Hide
+            && Self::extract_content_warning(event).is_some()
+    }
 
-        // クライアント作成
-        let client = Client::new(keys.clone());
+    /// bot を非表示にする設定で、作者が bot を名乗っているか
+    async fn is_hidden_bot(settings: &Arc<RwLock<AppSettings>>, profiles: &ProfileCache, pubkey: &PublicKey) -> bool {
+        settings.read().await.hide_bots
+            && profiles
+                .read()
+                .await
+                .get(&pubkey.to_hex())
+                .is_some_and(|profile| profile.bot)
+    }
 
-        // リレーに接続（設定から読み込んだリストを使用）
-        let relays = self.relays.read().await.clone();
-        println!("🌐 Connecting to {} relays...", relays.len());
-        for relay in &relays {
-            println!("  - {}", relay);
-            match client.add_relay(relay.as_str()).await {
-                Ok(_) => println!("    ✓ Added"),
-                Err(e) => println!("    ✗ Failed to add: {}", e),
-            }
+    /// 表示済みとして記録（作者本人により削除済みならfalse）
+    async fn mark_seen(deletions: &Deletions, event: &Event) -> bool {
+        let id = event.id.to_hex();
+        let pubkey = event.pubkey.to_hex();
+        let mut index = deletions.write().await;
+        if index.deleted.get(&id).is_some_and(|deleters| deleters.contains(&pubkey)) {
+            return false;
         }
+        index.seen.insert(id, pubkey);
+        true
+    }
 
-        client.connect().await;
-
-        // 接続されたリレーを確認
-        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-        println!("✅ Connected to relays (waiting 2s for connection to stabilize)");
+    /// 統合ストリームに表示できるkindのみリポスト元として扱う
+    fn displayable_repost_target(event: Event) -> Option<Event> {
+        if event.kind == Kind::TextNote || event.kind == Kind::ChannelMessage {
+            Some(event)
+        } else {
+            None
+        }
+    }
 
-        *self.keys.write().await = Some(keys);
-        *self.client.write().await = Some(client);
+    /// イベント受信チャンネルを設定
+    pub async fn set_event_sender(&self, sender: mpsc::UnboundedSender<NostrEvent>) {
+        *self.event_sender.write().await = Some(sender);
+    }
 
-        Ok(())
+    /// 設定ディレクトリのパスを取得
+    fn config_dir() -> Option<PathBuf> {
+        ProjectDirs::from("com", "gilga", "Gilga").map(|dirs| dirs.config_dir().to_path_buf())
     }
 
-    /// 統合ストリームを購読（kind:42 チャット + kind:1 投稿 + kind:0 プロフィール）
-    pub async fn subscribe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let client_guard = self.client.read().await;
-        let client = client_guard.as_ref().ok_or("Client not initialized")?;
+    /// 鍵ファイルのパスを取得
+    fn keys_path(dir: Option<&Path>) -> Option<PathBuf> {
+        dir.map(|dir| dir.join("keys.json"))
+    }
+
+    /// 鍵ファイルを読み込み（存在しなければ None）
+    fn read_stored_keys(dir: Option<&Path>) -> Result<Option<StoredKeys>, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(path) = Self::keys_path(dir) else {
+            return Ok(None);
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)?;
+        Ok(Some(serde_json::from_str(&data)?))
+    }
+
+    /// 鍵ファイルに書き込み（直前の鍵はバックアップとして残す）
+    /// 新しい内容より多くの秘密を平文で持つバックアップは残さない（passphrase があれば暗号化して残す）
+    fn write_stored_keys(
+        dir: Option<&Path>,
+        stored: &StoredKeys,
+        passphrase: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(path) = Self::keys_path(dir) {
+            let data = serde_json::to_string_pretty(stored)?;
+            storage::write_with_backup(&path, data.as_bytes(), |previous| {
+                Self::backup_form(previous, stored, passphrase)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// 鍵ファイルの以前の内容を、新しい内容に合わせてバックアップに残せる形にする（残せなければ None）
+    /// 単語列は新しい内容になければ消し、平文の秘密鍵は新しい内容が平文でなければ暗号化するか残さない
+    fn backup_form(previous: &[u8], stored: &StoredKeys, passphrase: Option<&str>) -> Option<Vec<u8>> {
+        let Ok(mut backup) = serde_json::from_slice::<StoredKeys>(previous) else {
+            // 読めない内容は復元にも使えないので、平文で保存していないなら残さない
+            return stored.secret_key.is_some().then(|| previous.to_vec());
+        };
+        if stored.mnemonic.is_none() {
+            backup.mnemonic = None;
+            if stored.encrypted_mnemonic.is_none() {
+                backup.encrypted_mnemonic = None;
+            }
+        }
+        if stored.secret_key.is_none() {
+            if let Some(hex) = backup.secret_key.take() {
+                let secret_key = SecretKey::from_hex(&hex).ok()?;
+                backup.ncryptsec = Some(secret_key.encrypt(passphrase?).ok()?.to_bech32().ok()?);
+            }
+        }
+        serde_json::to_string_pretty(&backup).ok().map(String::into_bytes)
+    }
+
+    /// 鍵を保存形式に変換（パスフレーズがあれば NIP-49 で暗号化）
+    fn to_stored_keys(
+        keys: &Keys,
+        passphrase: Option<&str>,
+    ) -> Result<StoredKeys, Box<dyn std::error::Error + Send + Sync>> {
+        match passphrase {
+            Some(passphrase) => Ok(StoredKeys {
+                ncryptsec: Some(keys.secret_key().encrypt(passphrase)?.to_bech32()?),
+                ..Default::default()
+            }),
+            None => Ok(StoredKeys {
+                secret_key: Some(keys.secret_key().to_secret_hex()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// 鍵と単語列を保存形式に変換（パスフレーズがあれば単語列も平文では残さず、自分の鍵で暗号化する）
+    fn to_stored_keys_with_mnemonic(
+        keys: &Keys,
+        passphrase: Option<&str>,
+        mnemonic: Option<String>,
+    ) -> Result<StoredKeys, Box<dyn std::error::Error + Send + Sync>> {
+        let mut stored = Self::to_stored_keys(keys, passphrase)?;
+        match (mnemonic, passphrase) {
+            (Some(mnemonic), Some(_)) => {
+                stored.encrypted_mnemonic = Some(nip44::encrypt(
+                    keys.secret_key(),
+                    &keys.public_key(),
+                    mnemonic,
+                    nip44::Version::V2,
+                )?);
+            }
+            (mnemonic, None) => stored.mnemonic = mnemonic,
+            (None, Some(_)) => {}
+        }
+        Ok(stored)
+    }
+
+    /// ncryptsec をパスフレーズで復号
+    fn decrypt_ncryptsec(
+        ncryptsec: &str,
+        passphrase: &str,
+    ) -> Result<Keys, Box<dyn std::error::Error + Send + Sync>> {
+        let encrypted = EncryptedSecretKey::from_bech32(ncryptsec)?;
+        let secret_key = encrypted.decrypt(passphrase).map_err(|_| "Wrong passphrase")?;
+        Ok(Keys::new(secret_key))
+    }
+
+    /// 保存された鍵を読み込み、なければ新規生成
+    /// パスフレーズ保護されていれば passphrase で復号し、リモート署名なら署名サーバーに接続する
+    async fn load_signer(
+        dir: Option<&Path>,
+        passphrase: Option<&str>,
+        use_mnemonic: bool,
+    ) -> Result<IdentitySigner, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(stored) = Self::read_stored_keys(dir)? {
+            // 既存の鍵を読み込み
+            if let Some(bunker) = stored.bunker {
+                let signer = Self::connect_remote_signer(&bunker).await?;
+                // 以前の版で nostrconnect:// のまま保存したものは、つながったら bunker:// で保存し直す
+                let reconnectable = Self::reconnectable_bunker(bunker.clone(), &signer).await?;
+                if reconnectable.uri != bunker.uri {
+                    let stored = StoredKeys {
+                        bunker: Some(reconnectable),
+                        ..Default::default()
+                    };
+                    Self::write_stored_keys(dir, &stored, None)?;
+                }
+                return Ok(signer);
+            }
+            if let Some(hex) = stored.secret_key {
+                let secret_key = SecretKey::from_hex(&hex)?;
+                return Ok(IdentitySigner::Local(Keys::new(secret_key)));
+            }
+            if let Some(ncryptsec) = stored.ncryptsec {
+                let passphrase = passphrase.ok_or("Keys are locked. Unlock with your passphrase first")?;
+                return Ok(IdentitySigner::Local(Self::decrypt_ncryptsec(&ncryptsec, passphrase)?));
+            }
+        }
+
+        // 新規生成して保存
+        let (keys, mnemonic) = Self::generate_keys(use_mnemonic)?;
+        let stored = StoredKeys {
+            mnemonic,
+            ..Self::to_stored_keys(&keys, None)?
+        };
+        Self::write_stored_keys(dir, &stored, None)?;
+
+        Ok(IdentitySigner::Local(keys))
+    }
+
+    /// 鍵を新規生成（use_mnemonic なら NIP-06 で単語列から導出し、その単語列も返す）
+    fn generate_keys(use_mnemonic: bool) -> Result<(Keys, Option<String>), Box<dyn std::error::Error + Send + Sync>> {
+        if !use_mnemonic {
+            return Ok((Keys::generate(), None));
+        }
+        let mnemonic = bip39::Mnemonic::generate(12)?.to_string();
+        let keys = Keys::from_mnemonic(mnemonic.clone(), None)?;
+        Ok((keys, Some(mnemonic)))
+    }
+
+    /// バックアップ用の単語列を取得（一度表示したら鍵ファイルから消す）
+    pub async fn take_mnemonic(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let dir = self.identity_dir().await;
+        let mut stored = Self::read_stored_keys(dir.as_deref())?.ok_or("Key file not found")?;
+        let keys = self.keys.read().await.clone();
+        let mnemonic = Self::pending_mnemonic(&stored, keys.as_ref())?
+            .ok_or("No seed phrase to show (already shown, or the key was not generated from one)")?;
+        stored.mnemonic = None;
+        stored.encrypted_mnemonic = None;
+        Self::write_stored_keys(dir.as_deref(), &stored, None)?;
+        Ok(mnemonic)
+    }
+
+    /// まだ表示していない単語列（暗号化して保持していれば鍵で復号する）
+    fn pending_mnemonic(
+        stored: &StoredKeys,
+        keys: Option<&Keys>,
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(mnemonic) = &stored.mnemonic {
+            return Ok(Some(mnemonic.clone()));
+        }
+        let Some(encrypted) = &stored.encrypted_mnemonic else {
+            return Ok(None);
+        };
+        let keys = keys.ok_or("Keys are locked. Unlock with your passphrase first")?;
+        Ok(Some(nip44::decrypt(keys.secret_key(), &keys.public_key(), encrypted)?))
+    }
+
+    /// 署名サーバー（NIP-46）に接続して公開鍵を取得
+    async fn connect_remote_signer(
+        bunker: &StoredBunker,
+    ) -> Result<IdentitySigner, Box<dyn std::error::Error + Send + Sync>> {
+        let uri = NostrConnectURI::parse(&bunker.uri)?;
+        let app_keys = Keys::new(SecretKey::from_hex(&bunker.app_secret_key)?);
+        let signer = NostrConnect::new(uri, app_keys, NIP46_TIMEOUT, None)?;
+        let public_key = signer.get_public_key().await?;
+        Ok(IdentitySigner::Remote { signer, public_key })
+    }
+
+    /// 再起動後もつながる形の接続情報（nostrconnect:// は署名サーバーからの最初の応答を待つ形なので、
+    /// つながった後は署名サーバーの pubkey を入れた bunker:// に置き換える）
+    async fn reconnectable_bunker(
+        bunker: StoredBunker,
+        signer: &IdentitySigner,
+    ) -> Result<StoredBunker, Box<dyn std::error::Error + Send + Sync>> {
+        let IdentitySigner::Remote { signer, .. } = signer else {
+            return Ok(bunker);
+        };
+        if !bunker.uri.starts_with("nostrconnect://") {
+            return Ok(bunker);
+        }
+        Ok(StoredBunker {
+            uri: signer.bunker_uri().await?.to_string(),
+            ..bunker
+        })
+    }
+
+    /// アプリ側の鍵と待ち受けるリレーから nostrconnect:// URI を作る
+    fn nostrconnect_uri(app_keys: &Keys, relay: &str) -> String {
+        let metadata = serde_json::json!({ "name": "gilga" }).to_string();
+        format!(
+            "nostrconnect://{}?relay={}&metadata={}",
+            app_keys.public_key().to_hex(),
+            url::form_urlencoded::byte_serialize(relay.as_bytes()).collect::<String>(),
+            url::form_urlencoded::byte_serialize(metadata.as_bytes()).collect::<String>(),
+        )
+    }
+
+    /// nostrconnect:// URI を発行（署名サーバー側に貼り付けて接続を承認してもらう）
+    pub async fn create_nostrconnect_uri(&self) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let relay = self
+            .relays
+            .read()
+            .await
+            .first()
+            .map(|relay| relay.url.clone())
+            .ok_or("No relay configured")?;
+        let app_keys = Keys::generate();
+        let uri = Self::nostrconnect_uri(&app_keys, &relay);
+        *self.pending_nostrconnect.write().await = Some(app_keys);
+        Ok(uri)
+    }
+
+    /// リモート署名（NIP-46）に切り替え（bunker:// か、発行済みの nostrconnect:// URI）
+    /// 以後 keys.json には秘密鍵を置かず、投稿やプロフィール更新の署名はすべて署名サーバーに任せる
+    pub async fn connect_bunker(&self, uri: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let uri = uri.trim();
+        let app_keys = if uri.starts_with("nostrconnect://") {
+            self.pending_nostrconnect
+                .write()
+                .await
+                .take()
+                .ok_or("Create a nostrconnect URI in gilga first")?
+        } else if uri.starts_with("bunker://") {
+            Keys::generate()
+        } else {
+            return Err("URI must start with bunker:// or nostrconnect://".into());
+        };
+
+        let bunker = StoredBunker {
+            uri: uri.to_string(),
+            app_secret_key: app_keys.secret_key().to_secret_hex(),
+        };
+        let signer = Self::connect_remote_signer(&bunker).await?;
+        let public_key = signer.public_key();
+        let bunker = Self::reconnectable_bunker(bunker, &signer).await?;
+
+        // 接続できてから保存（平文の鍵はバックアップにも残さない）
+        let stored = StoredKeys {
+            bunker: Some(bunker),
+            ..Default::default()
+        };
+        Self::write_stored_keys(self.identity_dir().await.as_deref(), &stored, None)?;
+
+        self.remember_identity_pubkey(&public_key).await?;
+        self.switch_signer(signer).await?;
+        Ok(public_key.to_bech32()?)
+    }
+
+    /// 鍵の保護状態を取得
+    pub async fn get_key_status(&self) -> KeyStatus {
+        let dir = self.identity_dir().await;
+        let stored = Self::read_stored_keys(dir.as_deref()).ok().flatten();
+        KeyStatus {
+            encrypted: stored.as_ref().is_some_and(|stored| stored.ncryptsec.is_some()),
+            unlocked: self.keys.read().await.is_some(),
+            remote: stored.as_ref().is_some_and(|stored| stored.bunker.is_some()),
+            mnemonic_pending: stored
+                .as_ref()
+                .is_some_and(|stored| stored.mnemonic.is_some() || stored.encrypted_mnemonic.is_some()),
+        }
+    }
+
+    /// パスフレーズで鍵を復号してメモリに読み込む（init の前に呼ぶ）
+    pub async fn unlock(&self, passphrase: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let stored = Self::read_stored_keys(self.identity_dir().await.as_deref())?.ok_or("Key file not found")?;
+        let ncryptsec = stored.ncryptsec.ok_or("Keys are not protected by a passphrase")?;
+        let keys = Self::decrypt_ncryptsec(&ncryptsec, passphrase)?;
+        let pubkey = keys.public_key().to_bech32().unwrap_or_default();
+        *self.public_key.write().await = Some(keys.public_key());
+        *self.keys.write().await = Some(keys);
+        Ok(pubkey)
+    }
+
+    /// パスフレーズを設定（平文で保存されている鍵を暗号化）
+    pub async fn set_passphrase(&self, passphrase: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if passphrase.is_empty() {
+            return Err("Passphrase must not be empty".into());
+        }
+        if self.get_key_status().await.encrypted {
+            return Err("Passphrase is already set".into());
+        }
+        let keys_guard = self.keys.read().await;
+        let keys = keys_guard.as_ref().ok_or("No local key (remote signer in use)")?;
+        // まだ表示していない単語列は暗号化して引き継ぐ
+        let dir = self.identity_dir().await;
+        let mnemonic = match Self::read_stored_keys(dir.as_deref())? {
+            Some(stored) => Self::pending_mnemonic(&stored, Some(keys))?,
+            None => None,
+        };
+        Self::write_stored_keys(
+            dir.as_deref(),
+            &Self::to_stored_keys_with_mnemonic(keys, Some(passphrase), mnemonic)?,
+            Some(passphrase),
+        )
+    }
+
+    /// パスフレーズを変更
+    pub async fn change_passphrase(
+        &self,
+        old_passphrase: &str,
+        new_passphrase: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if new_passphrase.is_empty() {
+            return Err("Passphrase must not be empty".into());
+        }
+        let dir = self.identity_dir().await;
+        let stored = Self::read_stored_keys(dir.as_deref())?.ok_or("Key file not found")?;
+        let ncryptsec = stored.ncryptsec.as_deref().ok_or("Keys are not protected by a passphrase")?;
+        let keys = Self::decrypt_ncryptsec(ncryptsec, old_passphrase)?;
+        let mnemonic = Self::pending_mnemonic(&stored, Some(&keys))?;
+        Self::write_stored_keys(
+            dir.as_deref(),
+            &Self::to_stored_keys_with_mnemonic(&keys, Some(new_passphrase), mnemonic)?,
+            Some(new_passphrase),
+        )
+    }
+
+    /// パスフレーズを解除（平文で保存し直す）
+    pub async fn remove_passphrase(&self, passphrase: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let dir = self.identity_dir().await;
+        let stored = Self::read_stored_keys(dir.as_deref())?.ok_or("Key file not found")?;
+        let ncryptsec = stored.ncryptsec.as_deref().ok_or("Keys are not protected by a passphrase")?;
+        let keys = Self::decrypt_ncryptsec(ncryptsec, passphrase)?;
+        let mnemonic = Self::pending_mnemonic(&stored, Some(&keys))?;
+        Self::write_stored_keys(dir.as_deref(), &Self::to_stored_keys_with_mnemonic(&keys, None, mnemonic)?, None)
+    }
+
+    /// 暗号化された秘密鍵を取得（エクスポート用、ncryptsec形式）
+    pub async fn get_encrypted_secret_key(&self) -> Option<String> {
+        Self::read_stored_keys(self.identity_dir().await.as_deref()).ok().flatten()?.ncryptsec
+    }
+
+    /// 初期化（鍵読み込み/生成 + リレー接続）
+    pub async fn init(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // 鍵を読み込み or 生成（unlock 済みならその鍵を使う）
+        let unlocked = self.keys.read().await.clone();
+        let use_mnemonic = self.settings.read().await.mnemonic_keys;
+        let signer = match unlocked {
+            Some(keys) => IdentitySigner::Local(keys),
+            None => Self::load_signer(self.identity_dir().await.as_deref(), None, use_mnemonic).await?,
+        };
+        let public_key = signer.public_key();
+        self.remember_identity_pubkey(&public_key).await?;
+
+        // クライアント作成
+        let client = match signer {
+            IdentitySigner::Local(keys) => {
+                *self.keys.write().await = Some(keys.clone());
+                Client::new(keys)
+            }
+            IdentitySigner::Remote { signer, .. } => Client::new(signer),
+        };
+        // 認証要求（NIP-42）にはリレーごとの設定に従って自分で応じる
+        client.automatic_authentication(false);
+
+        // リレーに接続（設定から読み込んだリストを使用）
+        let relays = self.relays.read().await.clone();
+        println!("🌐 Connecting to {} relays...", relays.len());
+        for relay in &relays {
+            println!("  - {}", relay.url);
+            match Self::add_client_relay(&client, relay).await {
+                Ok(_) => println!("    ✓ Added"),
+                Err(e) => println!("    ✗ Failed to add: {}", e),
+            }
+        }
+
+        client.connect().await;
+
+        // 接続されたリレーを確認
+        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+        println!("✅ Connected to relays (waiting 2s for connection to stabilize)");
+
+        *self.public_key.write().await = Some(public_key);
+        *self.client.write().await = Some(client);
+        self.prefetch_relay_info(relays.into_iter().map(|relay| relay.url).collect());
+
+        // 自分のプロフィール・フォロー・ミュートは流れてくるのを待たずに取得
+        if let Err(e) = self.fetch_own_lists().await {
+            println!("⚠️ Failed to fetch own profile and lists: {}", e);
+        }
+
+        Ok(())
+    }
+
+    /// 自分の kind:0 / kind:3 / ミュートリスト（kind:10000）/ リレーリスト（kind:10002）を取得してキャッシュ
+    /// ミュートリストはローカルのミュートに追加する（ローカルだけのミュートは消さない）
+    async fn fetch_own_lists(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client = self.client.read().await.clone().ok_or("Client not initialized")?;
+        let my_pubkey = self.public_key.read().await.ok_or("Keys not initialized")?;
+
+        let filter = Filter::new()
+            .kinds(vec![Kind::Metadata, Kind::ContactList, Kind::MuteList, Kind::RelayList])
+            .author(my_pubkey);
+        let events = client.fetch_events(filter, FETCH_TIMEOUT).await?;
+        // 置き換え可能イベントなので種類ごとに最新のものだけ使う
+        let latest = |kind: Kind| {
+            events
+                .iter()
+                .filter(|event| event.kind == kind)
+                .max_by_key(|event| event.created_at)
+        };
+
+        if let Some(event) = latest(Kind::Metadata) {
+            Self::remember_own_metadata(&self.own_metadata, event).await;
+            if let Some(profile) = Self::profile_from_event(event) {
+                let replaced = self.profiles.write().await.insert(my_pubkey.to_hex(), profile.clone());
+                println!("👤 Own profile fetched");
+                if replaced {
+                    self.emit(NostrEvent::MyProfile(profile)).await;
+                }
+            }
+        }
+
+        if let Some(event) = latest(Kind::ContactList) {
+            let followed: std::collections::HashSet<String> = Self::tag_values(event, "p").into_iter().collect();
+            println!("👥 Own follow list fetched: {} users", followed.len());
+            *self.follows.write().await = followed;
+            Self::request_outbox_update(&self.outbox_requests).await;
+        }
+
+        if let Some(event) = latest(Kind::MuteList) {
+            let added = {
+                let mut muted = self.muted.write().await;
+                let before = muted.len();
+                muted.extend(Self::tag_values(event, "p"));
+                muted.len() - before
+            };
+            println!("🔇 Own mute list fetched: {} new", added);
+            if added > 0 {
+                self.save_mute_list().await?;
+            }
+        }
+
+        // 公開済みのリレーリストにローカルにないものがあればマージを提案
+        let mut offer = None;
+        if let Some(event) = latest(Kind::RelayList) {
+            let relays = self.relays.read().await.clone();
+            let missing: Vec<RelayEntry> = Self::relay_entries_from_event(event)
+                .into_iter()
+                .filter(|remote| !relays.contains(remote))
+                .collect();
+            println!("📡 Own relay list fetched: {} not in local list", missing.len());
+            if !missing.is_empty() {
+                *self.pending_relay_list.write().await = Some(missing.clone());
+                offer = Some(missing);
+            }
+        }
+        let deferred = {
+            let mut sync = self.relay_list_sync.write().await;
+            sync.fetched = true;
+            sync.deferred
+        };
+        match offer {
+            Some(relays) => self.emit(NostrEvent::RelayListFound(RelayListOffer { relays })).await,
+            // ローカルのリストがリモートを含んでいれば、待たせていた変更を公開しても何も失わない
+            None if deferred => self.publish_relay_list().await,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// 統合ストリームを購読（kind:42 チャット + kind:1 投稿 + kind:6/16 リポスト + kind:5 削除）
+    pub async fn subscribe(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client_guard = self.client.read().await;
+        let client = client_guard.as_ref().ok_or("Client not initialized")?;
 
         // メッセージは過去1時間から取得
         let one_hour_ago = Timestamp::now().as_u64() - 3600; // 1時間前
         let messages_filter = Filter::new()
-            .kinds(vec![Kind::ChannelMessage, Kind::TextNote])
+            .kinds(vec![
+                Kind::ChannelMessage,
+                Kind::TextNote,
+                Kind::Repost,
+                Kind::GenericRepost,
+                Kind::EventDeletion,
+            ])
             .since(Timestamp::from(one_hour_ago))
             .limit(50); // 最大50件に制限
 
-        // プロフィールは過去のものも取得（表示名のため）
-        let profiles_filter = Filter::new()
-            .kind(Kind::Metadata)
-            .limit(100); // 減らす
-
-        println!("🔔 Subscribing to messages (last 1 hour, max 50) and profiles (max 100)...");
-        println!("   Filter: since={}, kinds=[1,42]", one_hour_ago);
+        // プロフィールは表示するメッセージの作者だけ、受信時にまとめて取得する（start_profile_fetcher）
+        println!("🔔 Subscribing to messages (last 1 hour, max 50)...");
+        println!("   Filter: since={}, kinds=[1,5,6,16,42]", one_hour_ago);
         client.subscribe(messages_filter, None).await?;
         println!("   ✓ Messages filter subscribed");
-        client.subscribe(profiles_filter, None).await?;
-        println!("   ✓ Profiles filter subscribed");
 
+        drop(client_guard);
+        self.subscribe_identity().await?;
+
+        Ok(())
+    }
+
+    /// 自分の公開鍵に依存する購読（通知・DM・自分のプロフィールとフォローリスト）
+    /// 固定のサブスクリプションIDを使うので、鍵を切り替えたときに呼び直すと上書きされる
+    async fn subscribe_identity(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client_guard = self.client.read().await;
+        let client = client_guard.as_ref().ok_or("Client not initialized")?;
+        let my_pubkey = self.public_key.read().await.ok_or("Keys not initialized")?;
+
+        // 自分のプロフィールとフォローリスト
+        let self_filter = Filter::new()
+            .kinds(vec![Kind::Metadata, Kind::ContactList])
+            .author(my_pubkey);
+        client
+            .subscribe_with_id(SubscriptionId::new(SELF_SUBSCRIPTION), self_filter, None)
+            .await?;
+        println!("   ✓ Own profile and follow list subscribed");
+
+        // 自分宛ての返信・メンション（前回の最新通知以降、なければ過去1日）
+        let one_day_ago = Timestamp::now().as_u64() - 86400;
+        let since = self
+            .notifications
+            .read()
+            .await
+            .first()
+            .map(|n| n.timestamp as u64)
+            .unwrap_or(one_day_ago);
+        let notifications_filter = Filter::new()
+            .kinds(vec![Kind::TextNote, Kind::ChannelMessage])
+            .pubkey(my_pubkey)
+            .since(Timestamp::from(since))
+            .limit(50);
+        client
+            .subscribe_with_id(SubscriptionId::new(NOTIFICATIONS_SUBSCRIPTION), notifications_filter, None)
+            .await?;
+        println!("   ✓ Notifications filter subscribed");
+
+        // 自分宛てのギフトラップ（created_at はランダムに過去へずらされるので期間は絞らない）
+        let dm_filter = Filter::new()
+            .kind(Kind::GiftWrap)
+            .pubkey(my_pubkey)
+            .limit(500);
+        client
+            .subscribe_with_id(SubscriptionId::new(DM_SUBSCRIPTION), dm_filter, None)
+            .await?;
+        println!("   ✓ Direct messages filter subscribed");
+
+        // 旧形式のDM（kind:4）は読み取りのみ。受信分と送信分の両方を取得
+        let legacy_in_filter = Filter::new()
+            .kind(Kind::EncryptedDirectMessage)
+            .pubkey(my_pubkey)
+            .limit(500);
+        let legacy_out_filter = Filter::new()
+            .kind(Kind::EncryptedDirectMessage)
+            .author(my_pubkey)
+            .limit(500);
+        client
+            .subscribe_with_id(SubscriptionId::new(LEGACY_DM_IN_SUBSCRIPTION), legacy_in_filter, None)
+            .await?;
+        client
+            .subscribe_with_id(SubscriptionId::new(LEGACY_DM_OUT_SUBSCRIPTION), legacy_out_filter, None)
+            .await?;
+        println!("   ✓ Legacy direct messages filter subscribed");
+
+        Ok(())
+    }
+
+    /// イベントストリームを開始（バックグラウンドでイベントを受信）
+    pub async fn start_listening(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let client = self.client.read().await.clone().ok_or("Client not initialized")?;
+        let sender = self.event_sender.clone();
+        let profiles = self.profiles.clone();
+        let muted = self.muted.clone();
+        let reposts = self.reposts.clone();
+        let deletions = self.deletions.clone();
+        let settings = self.settings.clone();
+        let notifications = self.notifications.clone();
+        let direct_messages = self.direct_messages.clone();
+        let follows = self.follows.clone();
+        let own_metadata = self.own_metadata.clone();
+        let public_key = self.public_key.clone();
+        let identity_dir = self.identity_dir.clone();
+        let profile_requests = self.profile_requests.clone();
+        let outbox = self.outbox.clone();
+        let outbox_requests = self.outbox_requests.clone();
+        let nip05 = self.nip05.clone();
+        let relay_auth = self.relay_auth.clone();
+        let dm_queue = self.dm_queue.clone();
+        self.start_profile_fetcher(client.clone()).await;
+        self.start_profile_maintenance(client.clone()).await;
+        self.start_outbox_router(client.clone()).await;
+        self.start_dm_decryptor(client.clone()).await;
+
+        tokio::spawn(async move {
+            println!("🎧 Event listener started");
+            let fetcher = client.clone();
+            client
+                .handle_notifications(|notification| {
+                    let client = fetcher.clone();
+                    let sender = sender.clone();
+                    let profiles = profiles.clone();
+                    let muted = muted.clone();
+                    let reposts = reposts.clone();
+                    let deletions = deletions.clone();
+                    let settings = settings.clone();
+                    let notifications = notifications.clone();
+                    let direct_messages = direct_messages.clone();
+                    let follows = follows.clone();
+                    let own_metadata = own_metadata.clone();
+                    let public_key = public_key.clone();
+                    let identity_dir = identity_dir.clone();
+                    let profile_requests = profile_requests.clone();
+                    let outbox = outbox.clone();
+                    let outbox_requests = outbox_requests.clone();
+                    let nip05 = nip05.clone();
+                    let relay_auth = relay_auth.clone();
+                    let dm_queue = dm_queue.clone();
+                    async move {
+                        if let RelayPoolNotification::Event { event, subscription_id, .. } = notification {
+                            println!("📬 Event received: kind={}", event.kind.as_u16());
+                            // 鍵の切り替えに追従するため毎回読む
+                            let Some(my_pubkey) = *public_key.read().await else {
+                                return Ok(false);
+                            };
+
+                            // ギフトラップ（kind:1059）と旧形式DM（kind:4）はDMとして処理し、公開ストリームには流さない
+                            // 復号は start_dm_decryptor のタスクに任せる
+                            if event.kind == Kind::GiftWrap || event.kind == Kind::EncryptedDirectMessage {
+                                if let Some(tx) = dm_queue.read().await.as_ref() {
+                                    if tx.try_send(*event).is_err() {
+                                        println!("⚠️ Direct message queue is full, message dropped");
+                                    }
+                                }
+                                return Ok(false);
+                            }
+
+                            // ミュートされたユーザーをスキップ
+                            let pubkey_hex = event.pubkey.to_hex();
+                            if muted.read().await.contains(&pubkey_hex) {
+                                return Ok(false);
+                            }
+
+                            // プロフィール（kind:0）の処理
+                            if event.kind == Kind::Metadata {
+                                println!("👤 Profile received for {}", &pubkey_hex[..8]);
+                                if event.pubkey == my_pubkey {
+                                    Self::remember_own_metadata(&own_metadata, &event).await;
+                                }
+                                if let Some(profile) = Self::profile_from_event(&event) {
+                                    let replaced = profiles.write().await.insert(pubkey_hex, profile.clone());
+                                    if replaced && event.pubkey == my_pubkey {
+                                        if let Some(tx) = sender.read().await.as_ref() {
+                                            let _ = tx.send(NostrEvent::MyProfile(profile));
+                                        }
+                                    }
+                                }
+                                return Ok(false);
+                            }
+
+                            // 自分のフォローリスト（kind:3）の処理
+                            if event.kind == Kind::ContactList {
+                                if event.pubkey == my_pubkey {
+                                    let followed: std::collections::HashSet<String> =
+                                        Self::tag_values(&event, "p").into_iter().collect();
+                                    println!("👥 Follow list received: {} users", followed.len());
+                                    *follows.write().await = followed;
+                                    Self::request_outbox_update(&outbox_requests).await;
+                                }
+                                return Ok(false);
+                            }
+
+                            // 削除要求（kind:5）の処理
+                            // 作者本人の削除要求のみ有効
+                            if event.kind == Kind::EventDeletion {
+                                let reason = if event.content.trim().is_empty() {
+                                    None
+                                } else {
+                                    Some(event.content.clone())
+                                };
+                                let mut index = deletions.write().await;
+                                for target_id in Self::tag_values(&event, "e") {
+                                    index.deleted.entry_or_default(target_id.clone()).insert(pubkey_hex.clone());
+                                    if index.seen.get(&target_id) == Some(&pubkey_hex) {
+                                        println!("🗑️ Message deleted: {}", target_id);
+                                        if let Some(tx) = sender.read().await.as_ref() {
+                                            let _ = tx.send(NostrEvent::Deleted(DeletedMessage {
+                                                id: target_id,
+                                                reason: reason.clone(),
+                                            }));
+                                        }
+                                    }
+                                }
+                                return Ok(false);
+                            }
+
+                            // リポスト（kind:6, kind:16）の処理
+                            // 元イベントの取得待ちで受信ループを止めないよう別タスクで処理
+                            if event.kind == Kind::Repost || event.kind == Kind::GenericRepost {
+                                tokio::spawn(async move {
+                                    let Some(original) = Self::resolve_repost(&client, &event).await else {
+                                        println!("🔁 Repost target not found: {}", event.id.to_hex());
+                                        return;
+                                    };
+                                    if muted.read().await.contains(&original.pubkey.to_hex()) {
+                                        return;
+                                    }
+                                    if Self::is_spam(&original.content) {
+                                        return;
+                                    }
+                                    if Self::is_hidden_by_content_warning(&settings, &original).await {
+                                        return;
+                                    }
+                                    if Self::is_hidden_bot(&settings, &profiles, &original.pubkey).await {
+                                        return;
+                                    }
+                                    if !Self::mark_seen(&deletions, &original).await {
+                                        return;
+                                    }
+
+                                    // 同じノートの連続リポストは1件にまとめる
+                                    let repost_count = {
+                                        let mut reposts = reposts.write().await;
+                                        let reposters = reposts.entry_or_default(original.id.to_hex());
+                                        reposters.insert(pubkey_hex);
+                                        reposters.len()
+                                    };
+
+                                    let profiles_guard = profiles.read().await;
+                                    let mut msg = Self::build_message(&original, &profiles_guard);
+                                    msg.reposted_by = Some(Self::format_author(&event.pubkey, &profiles_guard));
+                                    msg.repost_count = repost_count;
+                                    msg.verified_nip05 =
+                                        Self::check_nip05(&nip05, &sender, &profiles_guard, &msg.pubkey).await;
+                                    msg.look_alike_name = Self::is_look_alike_name(
+                                        &original.pubkey,
+                                        &profiles_guard,
+                                        &follows.read().await,
+                                        &my_pubkey,
+                                    );
+                                    Self::request_unknown_profiles(&profile_requests, &profiles_guard, &msg).await;
+                                    drop(profiles_guard);
+
+                                    println!("🔁 Repost of {} (x{})", msg.id, repost_count);
+                                    if let Some(tx) = sender.read().await.as_ref() {
+                                        let _ = tx.send(NostrEvent::Message(msg));
+                                    }
+                                });
+                                return Ok(false);
+                            }
+
+                            // メッセージ（kind:1, kind:42）の処理
+                            if event.kind != Kind::TextNote && event.kind != Kind::ChannelMessage {
+                                return Ok(false);
+                            }
+
+                            // スパムフィルタ
+                            if Self::is_spam(&event.content) {
+                                println!("🚫 Spam filtered: {}", &event.content[..50.min(event.content.len())]);
+                                return Ok(false);
+                            }
+
+                            // コンテンツ警告付きを非表示にする設定
+                            if Self::is_hidden_by_content_warning(&settings, &event).await {
+                                return Ok(false);
+                            }
+
+                            // bot を非表示にする設定
+                            if Self::is_hidden_bot(&settings, &profiles, &event.pubkey).await {
+                                return Ok(false);
+                            }
+
+                            // 自分宛てなら通知に追加
+                            let author = Self::format_author(&event.pubkey, &profiles.read().await);
+                            if let Some(notification) =
+                                Self::record_notification(&notifications, &identity_dir, &event, &my_pubkey, author).await
+                            {
+                                println!("🔔 Notification from {}", notification.notification.author);
+                                if let Some(tx) = sender.read().await.as_ref() {
+                                    let _ = tx.send(NostrEvent::Notification(notification));
+                                }
+                            }
+
+                            // 通知用の購読で届いた古いイベントはストリームに流さない
+                            if subscription_id.as_str() == NOTIFICATIONS_SUBSCRIPTION {
+                                return Ok(false);
+                            }
+
+                            // 削除済みのイベントは表示しない
+                            if !Self::mark_seen(&deletions, &event).await {
+                                return Ok(false);
+                            }
+
+                            // 返信ならスレッドの参加者の書き込み先リレーも購読する
+                            Self::record_thread_participants(&outbox, &outbox_requests, &event).await;
+
+                            let profiles_guard = profiles.read().await;
+                            let mut msg = Self::build_message(&event, &profiles_guard);
+                            msg.verified_nip05 = Self::check_nip05(&nip05, &sender, &profiles_guard, &msg.pubkey).await;
+                            msg.look_alike_name = Self::is_look_alike_name(
+                                &event.pubkey,
+                                &profiles_guard,
+                                &follows.read().await,
+                                &my_pubkey,
+                            );
+                            Self::request_unknown_profiles(&profile_requests, &profiles_guard, &msg).await;
+                            drop(profiles_guard);
+
+                            if let Some(ctx) = &msg.context {
+                                println!("📨 Received event: {} from {} [{}]", msg.content, msg.author, ctx);
+                            } else {
+                                println!("📨 Received event: {} from {}", msg.content, msg.author);
+                            }
+
+                            if let Some(tx) = sender.read().await.as_ref() {
+                                let _ = tx.send(NostrEvent::Message(msg));
+                            }
+                        } else if let RelayPoolNotification::Message { relay_url: url, message } = notification {
+                            // リレーからの認証要求（NIP-42）と、その結果
+                            let url = relay_url::normalize(url.as_str()).unwrap_or_else(|_| url.to_string());
+                            match message {
+                                RelayMessage::Auth { challenge } => {
+                                    println!("🔑 Auth challenge from {}", url);
+                                    let challenge = challenge.to_string();
+                                    // 署名（リモート署名だと承認待ちになる）で受信ループを止めないよう別タスクで処理
+                                    tokio::spawn(async move {
+                                        Self::handle_auth_challenge(&client, &settings, &relay_auth, &sender, &url, challenge)
+                                            .await;
+                                    });
+                                }
+                                RelayMessage::Ok { event_id, status, message } => {
+                                    Self::handle_auth_result(&client, &relay_auth, &sender, &url, event_id, status, &message)
+                                        .await;
+                                }
+                                _ => {}
+                            }
+                        } else {
+                            println!("🔔 Non-event notification received");
+                        }
+                        Ok(false) // 継続
+                    }
+                })
+                .await
+        });
+
+        Ok(())
+    }
+
+    /// 作者の NIP-05 を確認（確認済みなら識別子を返し、未確認なら裏で確認して一致したら nip05-verified を送る）
+    async fn check_nip05(
+        verifier: &Arc<Nip05Verifier>,
+        sender: &EventSender,
+        profiles: &ProfileStore,
+        pubkey_hex: &str,
+    ) -> Option<String> {
+        let identifier = profiles.get(pubkey_hex)?.nip05.clone().filter(|nip05| !nip05.is_empty())?;
+        if let Some(verified) = verifier.cached(&identifier, pubkey_hex).await {
+            return verified.then_some(identifier);
+        }
+
+        if verifier.begin(&identifier).await {
+            let verifier = verifier.clone();
+            let sender = sender.clone();
+            let pubkey_hex = pubkey_hex.to_string();
+            tokio::spawn(async move {
+                let verified = verifier.verify(&identifier, &pubkey_hex).await;
+                verifier.finish(&identifier).await;
+                if !verified {
+                    return;
+                }
+                println!("✅ NIP-05 verified: {}", identifier);
+                if let Some(tx) = sender.read().await.as_ref() {
+                    let _ = tx.send(NostrEvent::Nip05Verified(Nip05Verified {
+                        pubkey: pubkey_hex,
+                        nip05: identifier,
+                    }));
+                }
+            });
+        }
+        None
+    }
+
+    /// NIP-05 識別子（name@domain）からユーザーを探す
+    pub async fn lookup_nip05(&self, identifier: &str) -> Result<Nip05Lookup, Box<dyn std::error::Error + Send + Sync>> {
+        self.nip05
+            .lookup(identifier)
+            .await?
+            .ok_or_else(|| "No user found for this identifier".into())
+    }
+
+    /// 作者と本文中で言及されたユーザーのうち、プロフィール未取得のものを取得待ちに入れる
+    async fn request_unknown_profiles(
+        requests: &ProfileRequests,
+        profiles: &ProfileStore,
+        msg: &NostrMessage,
+    ) {
+        let requests = requests.read().await;
+        let Some(tx) = requests.as_ref() else {
+            return;
+        };
+        let mentioned = msg.segments.iter().filter_map(|segment| match segment {
+            ContentSegment::MentionProfile { pubkey, .. } => Some(pubkey.as_str()),
+            _ => None,
+        });
+        for pubkey_hex in std::iter::once(msg.pubkey.as_str()).chain(mentioned) {
+            if profiles.contains_key(pubkey_hex) {
+                continue;
+            }
+            if let Ok(pubkey) = PublicKey::from_hex(pubkey_hex) {
+                let _ = tx.send(pubkey);
+            }
+        }
+    }
+
+    /// 未知の作者のプロフィールを一定間隔でまとめて取得し、届いたら author-updated を送る
+    /// 一度問い合わせた作者は（見つからなくても）再度問い合わせない
+    async fn start_profile_fetcher(&self, client: Client) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<PublicKey>();
+        *self.profile_requests.write().await = Some(tx);
+        let profiles = self.profiles.clone();
+        let sender = self.event_sender.clone();
+        let checks = self.author_checks();
+
+        tokio::spawn(async move {
+            let mut requested: std::collections::HashSet<PublicKey> = std::collections::HashSet::new();
+            while let Some(first) = rx.recv().await {
+                // 少し待って、その間に届いた依頼をまとめる
+                tokio::time::sleep(PROFILE_BATCH_INTERVAL).await;
+                let mut batch = vec![first];
+                while let Ok(pubkey) = rx.try_recv() {
+                    batch.push(pubkey);
+                }
+                {
+                    let profiles = profiles.read().await;
+                    batch.retain(|pubkey| !profiles.contains_key(&pubkey.to_hex()) && requested.insert(*pubkey));
+                }
+
+                for chunk in batch.chunks(PROFILE_BATCH_SIZE) {
+                    if let Err(e) = Self::fetch_profile_batch(&client, &profiles, &sender, &checks, chunk).await {
+                        // 失敗した分は次に依頼されたときにやり直す
+                        println!("⚠️ Profile fetch failed: {}", e);
+                        for pubkey in chunk {
+                            requested.remove(pubkey);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// キャッシュを定期的に保存し、古くなったプロフィールを少しずつ取り直す
+    async fn start_profile_maintenance(&self, client: Client) {
+        let profiles = self.profiles.clone();
+        let sender = self.event_sender.clone();
+        let settings = self.settings.clone();
+        let checks = self.author_checks();
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PROFILE_MAINTENANCE_INTERVAL).await;
+
+                let max_age = settings.read().await.profile_refresh_hours * 3600;
+                let stale: Vec<PublicKey> = profiles
+                    .read()
+                    .await
+                    .stale(max_age, PROFILE_BATCH_SIZE)
+                    .iter()
+                    .filter_map(|pubkey_hex| PublicKey::from_hex(pubkey_hex).ok())
+                    .collect();
+                if !stale.is_empty() {
+                    println!("👤 Refreshing {} stale profiles", stale.len());
+                    if let Err(e) = Self::fetch_profile_batch(&client, &profiles, &sender, &checks, &stale).await {
+                        println!("⚠️ Profile refresh failed: {}", e);
+                    }
+                }
+
+                if let Err(e) = Self::save_profiles(&profiles).await {
+                    println!("⚠️ Failed to save profile cache: {}", e);
+                }
+            }
+        });
+    }
+
+    /// 届いたプロフィールで作者を確かめ直すのに使う状態
+    fn author_checks(&self) -> AuthorChecks {
+        AuthorChecks {
+            follows: self.follows.clone(),
+            public_key: self.public_key.clone(),
+            nip05: self.nip05.clone(),
+            settings: self.settings.clone(),
+        }
+    }
+
+    /// 指定した作者の kind:0 をまとめて取得してキャッシュし、名前が変わったものは author-updated を送る
+    /// 名前が分かって初めて判定できる、見分けにくい名前かどうか・NIP-05・bot かどうかもここで確かめ直す
+    async fn fetch_profile_batch(
+        client: &Client,
+        profiles: &ProfileCache,
+        sender: &EventSender,
+        checks: &AuthorChecks,
+        pubkeys: &[PublicKey],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let filter = Filter::new().kind(Kind::Metadata).authors(pubkeys.to_vec());
+        let events = client.fetch_events(filter, FETCH_TIMEOUT).await?;
+
+        // 作者ごとに最新の kind:0 だけ使う
+        let mut latest: HashMap<PublicKey, Event> = HashMap::new();
+        for event in events {
+            let is_newer = match latest.get(&event.pubkey) {
+                Some(current) => event.created_at > current.created_at,
+                None => true,
+            };
+            if is_newer {
+                latest.insert(event.pubkey, event);
+            }
+        }
+        println!("👤 Fetched {} of {} requested profiles", latest.len(), pubkeys.len());
+
+        let follows = checks.follows.read().await.clone();
+        let my_pubkey = *checks.public_key.read().await;
+        let hide_bots = checks.settings.read().await.hide_bots;
+        let mut profiles = profiles.write().await;
+        let requested: Vec<String> = pubkeys.iter().map(|pubkey| pubkey.to_hex()).collect();
+        profiles.mark_fetched(&requested);
+        for (pubkey, event) in latest {
+            let Some(profile) = Self::profile_from_event(&event) else {
+                continue;
+            };
+            let hidden_bot = hide_bots && profile.bot;
+            if !profiles.insert(pubkey.to_hex(), profile) {
+                continue;
+            }
+            if let Some(tx) = sender.read().await.as_ref() {
+                let _ = tx.send(NostrEvent::AuthorUpdated(AuthorUpdated {
+                    pubkey: pubkey.to_hex(),
+                    author: Self::format_author(&pubkey, &profiles),
+                    look_alike_name: my_pubkey
+                        .is_some_and(|my_pubkey| Self::is_look_alike_name(&pubkey, &profiles, &follows, &my_pubkey)),
+                    hidden_bot,
+                }));
+            }
+            if hidden_bot {
+                continue;
+            }
+
+            // 表示済みのメッセージにも反映されるよう、確認済みなら nip05-verified を送る（未確認なら裏で確認して送る）
+            let pubkey_hex = pubkey.to_hex();
+            if let Some(nip05) = Self::check_nip05(&checks.nip05, sender, &profiles, &pubkey_hex).await {
+                if let Some(tx) = sender.read().await.as_ref() {
+                    let _ = tx.send(NostrEvent::Nip05Verified(Nip05Verified {
+                        pubkey: pubkey_hex,
+                        nip05,
+                    }));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// リレーからの認証要求（NIP-42）をリレーごとの設定に従って処理
+    async fn handle_auth_challenge(
+        client: &Client,
+        settings: &Arc<RwLock<AppSettings>>,
+        relay_auth: &RelayAuthTracker,
+        sender: &EventSender,
+        url: &str,
+        challenge: String,
+    ) {
+        let policy = settings.read().await.relay_auth.get(url).copied().unwrap_or_default();
+        match policy {
+            AuthPolicy::Always => Self::authenticate(client, relay_auth, sender, url, challenge).await,
+            AuthPolicy::Ask => {
+                Self::update_relay_auth(client, relay_auth, sender, url, |auth| {
+                    auth.state = RelayAuthState::AwaitingApproval;
+                    auth.challenge = Some(challenge);
+                })
+                .await
+            }
+            AuthPolicy::Never => {
+                Self::update_relay_auth(client, relay_auth, sender, url, |auth| {
+                    auth.state = RelayAuthState::Declined;
+                    auth.challenge = None;
+                })
+                .await
+            }
+        }
+    }
+
+    /// 今のアイデンティティで署名した kind:22242 を送って認証する
+    async fn authenticate(client: &Client, relay_auth: &RelayAuthTracker, sender: &EventSender, url: &str, challenge: String) {
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = async {
+            let relay_url = RelayUrl::parse(url)?;
+            let event = client
+                .sign_event_builder(EventBuilder::auth(challenge, relay_url.clone()))
+                .await?;
+            let event_id = event.id;
+            Self::update_relay_auth(client, relay_auth, sender, url, |auth| {
+                auth.state = RelayAuthState::Authenticating;
+                auth.challenge = None;
+                auth.auth_event = Some(event_id);
+                auth.message = None;
+            })
+            .await;
+            client.send_msg_to([relay_url], ClientMessage::auth(event)).await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            println!("⚠️ Failed to authenticate to {}: {}", url, e);
+            Self::update_relay_auth(client, relay_auth, sender, url, |auth| {
+                auth.state = RelayAuthState::Failed;
+                auth.message = Some(e.to_string());
+            })
+            .await;
+        }
+    }
+
+    /// 送った kind:22242 への OK を処理（認証できたら、認証前に断られた購読をやり直す）
+    async fn handle_auth_result(
+        client: &Client,
+        relay_auth: &RelayAuthTracker,
+        sender: &EventSender,
+        url: &str,
+        event_id: EventId,
+        accepted: bool,
+        message: &str,
+    ) {
+        let is_auth_event = relay_auth
+            .read()
+            .await
+            .get(url)
+            .is_some_and(|auth| auth.auth_event == Some(event_id));
+        if !is_auth_event {
+            return;
+        }
+
+        if accepted {
+            println!("🔑 Authenticated to {}", url);
+            Self::update_relay_auth(client, relay_auth, sender, url, |auth| {
+                auth.state = RelayAuthState::Authenticated;
+                auth.message = None;
+            })
+            .await;
+            if let Ok(relay) = client.relay(url).await {
+                if let Err(e) = relay.resubscribe().await {
+                    println!("⚠️ Failed to resubscribe on {}: {}", url, e);
+                }
+            }
+        } else {
+            println!("⚠️ Authentication rejected by {}: {}", url, message);
+            Self::update_relay_auth(client, relay_auth, sender, url, |auth| {
+                auth.state = RelayAuthState::Failed;
+                auth.message = Some(message.to_string());
+            })
+            .await;
+        }
+    }
+
+    /// リレーの認証の状態を更新して relay-status を送る
+    async fn update_relay_auth(
+        client: &Client,
+        relay_auth: &RelayAuthTracker,
+        sender: &EventSender,
+        url: &str,
+        update: impl FnOnce(&mut RelayAuth),
+    ) {
+        let (auth, message) = {
+            let mut relay_auth = relay_auth.write().await;
+            let entry = relay_auth.entry(url.to_string()).or_default();
+            update(entry);
+            (entry.state, entry.message.clone())
+        };
+        let connection = match client.relay(url).await {
+            Ok(relay) => relay.status().to_string(),
+            Err(_) => "Disconnected".to_string(),
+        };
+        if let Some(tx) = sender.read().await.as_ref() {
+            let _ = tx.send(NostrEvent::RelayStatus(RelayStatusReport {
+                url: url.to_string(),
+                connection,
+                auth,
+                message,
+            }));
+        }
+    }
+
+    /// 接続中のリレーの状態（未接続なら設定済みのリレーを未接続として返す）
+    pub async fn get_relay_statuses(&self) -> Vec<RelayStatusReport> {
+        let mut connections: Vec<(String, String)> = match self.client.read().await.as_ref() {
+            Some(client) => client
+                .relays()
+                .await
+                .into_iter()
+                .map(|(url, relay)| (url.to_string(), relay.status().to_string()))
+                .collect(),
+            None => Vec::new(),
+        };
+        for relay in self.relays.read().await.iter() {
+            if !connections.iter().any(|(url, _)| relay_url::normalize(url).ok().as_ref() == Some(&relay.url)) {
+                connections.push((relay.url.clone(), "Disconnected".to_string()));
+            }
+        }
+        connections.sort();
+
+        let relay_auth = self.relay_auth.read().await;
+        connections
+            .into_iter()
+            .map(|(url, connection)| {
+                let url = relay_url::normalize(&url).unwrap_or(url);
+                let auth = relay_auth.get(&url);
+                RelayStatusReport {
+                    connection,
+                    auth: auth.map_or(RelayAuthState::NotRequired, |auth| auth.state),
+                    message: auth.and_then(|auth| auth.message.clone()),
+                    url,
+                }
+            })
+            .collect()
+    }
+
+    /// リレーごとの認証要求への応じ方を変更（確認待ちの要求があれば新しい設定で処理）
+    pub async fn set_relay_auth_policy(
+        &self,
+        url: &str,
+        policy: AuthPolicy,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let url = relay_url::normalize(url)?;
+        {
+            let mut settings = self.settings.write().await;
+            if policy == AuthPolicy::Ask {
+                settings.relay_auth.remove(&url);
+            } else {
+                settings.relay_auth.insert(url.clone(), policy);
+            }
+        }
+        self.save_settings().await?;
+
+        if policy != AuthPolicy::Ask {
+            self.respond_relay_auth(&url, policy == AuthPolicy::Always).await?;
+        }
+        Ok(())
+    }
+
+    /// 確認待ちの認証要求に応じる・断る（確認待ちでなければ何もしない）
+    pub async fn respond_relay_auth(&self, url: &str, allow: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let url = relay_url::normalize(url)?;
+        let client = self.client.read().await.clone().ok_or("Client not initialized")?;
+        let challenge = {
+            let mut relay_auth = self.relay_auth.write().await;
+            match relay_auth.get_mut(&url) {
+                Some(auth) if auth.state == RelayAuthState::AwaitingApproval => auth.challenge.take(),
+                _ => None,
+            }
+        };
+        let Some(challenge) = challenge else {
+            return Ok(());
+        };
+
+        if allow {
+            Self::authenticate(&client, &self.relay_auth, &self.event_sender, &url, challenge).await;
+        } else {
+            Self::update_relay_auth(&client, &self.relay_auth, &self.event_sender, &url, |auth| {
+                auth.state = RelayAuthState::Declined;
+            })
+            .await;
+        }
         Ok(())
     }
 
-    /// イベントストリームを開始（バックグラウンドでイベントを受信）
-    pub async fn start_listening(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let client = self.client.read().await.clone().ok_or("Client not initialized")?;
-        let sender = self.event_sender.clone();
-        let profiles = self.profiles.clone();
+    /// アウトボックスの購読の組み直しを依頼（未接続なら何もしない）
+    async fn request_outbox_update(requests: &OutboxRequests) {
+        if let Some(tx) = requests.read().await.as_ref() {
+            let _ = tx.send(());
+        }
+    }
+
+    /// 返信（kind:1 で e タグあり）の作者と p タグのユーザーをスレッドの参加者として記録
+    async fn record_thread_participants(outbox: &OutboxCache, requests: &OutboxRequests, event: &Event) {
+        if event.kind != Kind::TextNote || Self::tag_values(event, "e").is_empty() {
+            return;
+        }
+        let added = {
+            let mut outbox = outbox.write().await;
+            std::iter::once(event.pubkey.to_hex())
+                .chain(Self::tag_values(event, "p"))
+                .filter(|pubkey_hex| PublicKey::from_hex(pubkey_hex).is_ok())
+                .fold(false, |added, pubkey_hex| outbox.add_participant(&pubkey_hex) || added)
+        };
+        if added {
+            Self::request_outbox_update(requests).await;
+        }
+    }
+
+    /// 届いたDMを別タスクで順に復号する（署名サーバーとの往復で受信ループを止めない）
+    async fn start_dm_decryptor(&self, client: Client) {
+        let (tx, mut rx) = mpsc::channel::<Event>(DM_QUEUE_CAPACITY);
+        *self.dm_queue.write().await = Some(tx);
+        let keys = self.keys.clone();
+        let public_key = self.public_key.clone();
         let muted = self.muted.clone();
+        let direct_messages = self.direct_messages.clone();
+        let deferred = self.deferred_dms.clone();
+        let sender = self.event_sender.clone();
 
         tokio::spawn(async move {
-            println!("🎧 Event listener started");
-            client
-                .handle_notifications(|notification| {
-                    let sender = sender.clone();
-                    let profiles = profiles.clone();
-                    let muted = muted.clone();
-                    async move {
-                        if let RelayPoolNotification::Event { event, .. } = notification {
-                            println!("📬 Event received: kind={}", event.kind.as_u16());
-                            // ミュートされたユーザーをスキップ
-                            let pubkey_hex = event.pubkey.to_hex();
-                            if muted.read().await.contains(&pubkey_hex) {
-                                return Ok(false);
-                            }
+            while let Some(event) = rx.recv().await {
+                // リモート署名では1件ごとに署名サーバーとの往復（と承認）が要るので、DMを開くまで復号しない
+                if keys.read().await.is_none() {
+                    let mut deferred = deferred.write().await;
+                    deferred.push_back(event);
+                    if deferred.len() > DM_QUEUE_CAPACITY {
+                        deferred.pop_front();
+                    }
+                    continue;
+                }
+                let Some(my_pubkey) = *public_key.read().await else {
+                    continue;
+                };
+                Self::process_direct_message(&client, &event, &my_pubkey, &muted, &direct_messages, &sender).await;
+            }
+        });
+    }
 
-                            // プロフィール（kind:0）の処理
-                            if event.kind == Kind::Metadata {
-                                println!("👤 Profile received for {}", &pubkey_hex[..8]);
-                                if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&event.content) {
-                                    let profile = Profile {
-                                        name: metadata.get("name").and_then(|v| v.as_str()).map(String::from),
-                                        display_name: metadata.get("display_name").and_then(|v| v.as_str()).map(String::from),
-                                        about: metadata.get("about").and_then(|v| v.as_str()).map(String::from),
-                                        picture: metadata.get("picture").and_then(|v| v.as_str()).map(String::from),
-                                        website: metadata.get("website").and_then(|v| v.as_str()).map(String::from),
-                                        nip05: metadata.get("nip05").and_then(|v| v.as_str()).map(String::from),
-                                    };
-                                    profiles.write().await.insert(pubkey_hex, profile);
-                                }
-                                return Ok(false);
-                            }
+    /// リモート署名中に届いて後回しにしたDMを復号する
+    async fn decrypt_deferred_dms(&self) {
+        let pending: Vec<Event> = self.deferred_dms.write().await.drain(..).collect();
+        if pending.is_empty() {
+            return;
+        }
+        let Some(client) = self.client.read().await.clone() else {
+            return;
+        };
+        let Some(my_pubkey) = *self.public_key.read().await else {
+            return;
+        };
+        println!("✉️ Decrypting {} deferred direct messages", pending.len());
+        for event in pending {
+            Self::process_direct_message(
+                &client,
+                &event,
+                &my_pubkey,
+                &self.muted,
+                &self.direct_messages,
+                &self.event_sender,
+            )
+            .await;
+        }
+    }
 
-                            // メッセージ（kind:1, kind:42）の処理
-                            // スパムフィルタ
-                            if Self::is_spam(&event.content) {
-                                println!("🚫 Spam filtered: {}", &event.content[..50.min(event.content.len())]);
-                                return Ok(false);
-                            }
+    /// DM（ギフトラップか旧形式）を復号して保存し、新しいものなら direct-message を送る
+    async fn process_direct_message(
+        client: &Client,
+        event: &Event,
+        my_pubkey: &PublicKey,
+        muted: &MuteList,
+        direct_messages: &DirectMessageStore,
+        sender: &EventSender,
+    ) {
+        let dm = if event.kind == Kind::GiftWrap {
+            Self::unwrap_direct_message(client, event, my_pubkey).await
+        } else {
+            Self::decrypt_legacy_direct_message(client, event, my_pubkey).await
+        };
+        let Some(dm) = dm else {
+            return;
+        };
+        if muted.read().await.contains(&dm.sender) {
+            return;
+        }
+        if Self::store_direct_message(direct_messages, dm.clone()).await {
+            println!("✉️ Direct message with {}", &dm.peer[..8]);
+            if let Some(tx) = sender.read().await.as_ref() {
+                let _ = tx.send(NostrEvent::DirectMessage(dm));
+            }
+        }
+    }
 
-                            let is_post = event.kind == Kind::TextNote;
-                            let profiles_guard = profiles.read().await;
-                            let author = Self::format_author(&event.pubkey, &profiles_guard);
-                            drop(profiles_guard);
+    /// フォロー中の作者とスレッドの参加者を、それぞれが書き込むリレー（NIP-65）で購読する
+    /// 設定済みのリレーで拾える作者はそこで購読し、残りは少ないリレーで多くの作者を拾えるよう選んで接続する
+    async fn start_outbox_router(&self, client: Client) {
+        let (tx, mut rx) = mpsc::unbounded_channel::<()>();
+        let _ = tx.send(()); // 起動時に一度組み立てる
+        *self.outbox_requests.write().await = Some(tx);
+        let outbox = self.outbox.clone();
+        let follows = self.follows.clone();
+        let muted = self.muted.clone();
+        let relays = self.relays.clone();
+        let public_key = self.public_key.clone();
 
-                            // コンテキスト情報を取得（チャンネルID、リプライ先など）
-                            let context = Self::extract_context(&event);
-
-                            let msg = NostrMessage {
-                                id: event.id.to_hex(),
-                                pubkey: event.pubkey.to_hex(),
-                                author,
-                                content: event.content.clone(),
-                                timestamp: event.created_at.as_u64() as i64,
-                                is_post,
-                                context: context.clone(),
-                            };
+        tokio::spawn(async move {
+            // 現在購読しているリレー → 作者
+            let mut active: HashMap<String, Vec<String>> = HashMap::new();
+            while rx.recv().await.is_some() {
+                // 少し待って、その間に届いた依頼をまとめる
+                tokio::time::sleep(OUTBOX_UPDATE_INTERVAL).await;
+                while rx.try_recv().is_ok() {}
+
+                let my_pubkey = public_key.read().await.map(|pubkey| pubkey.to_hex());
+                let mut authors: Vec<String> = follows.read().await.iter().cloned().collect();
+                authors.extend(outbox.read().await.participants().cloned());
+                authors.sort();
+                authors.dedup();
+                {
+                    let muted = muted.read().await;
+                    authors.retain(|author| Some(author) != my_pubkey.as_ref() && !muted.contains(author));
+                }
 
-                            if let Some(ctx) = &context {
-                                println!("📨 Received event: {} from {} [{}]", msg.content, msg.author, ctx);
-                            } else {
-                                println!("📨 Received event: {} from {}", msg.content, msg.author);
-                            }
+                // まだ問い合わせていない作者の kind:10002 を取得
+                let unknown: Vec<PublicKey> = {
+                    let outbox = outbox.read().await;
+                    authors
+                        .iter()
+                        .filter(|author| !outbox.contains(author))
+                        .filter_map(|author| PublicKey::from_hex(author).ok())
+                        .collect()
+                };
+                for chunk in unknown.chunks(PROFILE_BATCH_SIZE) {
+                    if let Err(e) = Self::fetch_write_relays(&client, &outbox, chunk).await {
+                        println!("⚠️ Relay list fetch failed: {}", e);
+                    }
+                }
 
-                            if let Some(tx) = sender.read().await.as_ref() {
-                                let _ = tx.send(msg);
-                            }
-                        } else {
-                            println!("🔔 Non-event notification received");
-                        }
-                        Ok(false) // 継続
+                let configured: Vec<String> = relays
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|relay| relay.read)
+                    .map(|relay| relay.url.clone())
+                    .collect();
+                let selection = outbox
+                    .read()
+                    .await
+                    .select_relays(&authors, &configured, OUTBOX_MAX_EXTRA_RELAYS);
+                let mut next: HashMap<String, Vec<String>> = selection
+                    .assignments
+                    .into_iter()
+                    .map(|assignment| (assignment.relay, assignment.authors))
+                    .collect();
+                // 書き込み先が分からない作者は設定済みのリレーで購読
+                if !selection.uncovered.is_empty() {
+                    for relay in &configured {
+                        let authors = next.entry(relay.clone()).or_default();
+                        authors.extend(selection.uncovered.iter().cloned());
+                        authors.sort();
+                        authors.dedup();
                     }
-                })
-                .await
+                }
+                if next == active {
+                    continue;
+                }
+
+                Self::apply_outbox_subscriptions(&client, &configured, &active, &next).await;
+                let extra = next.keys().filter(|relay| !configured.contains(relay)).count();
+                println!("📡 Outbox: {} authors on {} relays ({} extra)", authors.len(), next.len(), extra);
+                active = next;
+            }
         });
+    }
 
+    /// 作者の kind:10002 をまとめて取得し、書き込み先リレーを記録（見つからなかった作者もそのことを記録）
+    async fn fetch_write_relays(
+        client: &Client,
+        outbox: &OutboxCache,
+        pubkeys: &[PublicKey],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let filter = Filter::new().kind(Kind::RelayList).authors(pubkeys.to_vec());
+        let events = client.fetch_events(filter, FETCH_TIMEOUT).await?;
+
+        let mut outbox = outbox.write().await;
+        for event in events {
+            let write_relays: Vec<String> = Self::relay_entries_from_event(&event)
+                .into_iter()
+                .filter(|relay| relay.write)
+                .map(|relay| relay.url)
+                .collect();
+            outbox.insert(event.pubkey.to_hex(), write_relays, event.created_at.as_u64());
+        }
+        for pubkey in pubkeys {
+            outbox.mark_missing(&pubkey.to_hex());
+        }
+        println!("📡 Fetched relay lists for {} authors", pubkeys.len());
         Ok(())
     }
 
-    /// メッセージ送信
+    /// 作者ごとの購読を新しい割り当てに合わせる（変わらないリレーはそのまま）
+    async fn apply_outbox_subscriptions(
+        client: &Client,
+        configured: &[String],
+        active: &HashMap<String, Vec<String>>,
+        next: &HashMap<String, Vec<String>>,
+    ) {
+        // 使わなくなったリレーの購読をやめ、追加で接続したリレーは切断
+        for relay in active.keys().filter(|relay| !next.contains_key(*relay)) {
+            client
+                .unsubscribe(&SubscriptionId::new(format!("{}{}", OUTBOX_SUBSCRIPTION_PREFIX, relay)))
+                .await;
+            if !configured.contains(relay) {
+                let _ = client.remove_relay(relay.as_str()).await;
+            }
+        }
+
+        // 新しく使うリレーに接続（自分の投稿は書き込まないので読み込み用として追加）
+        let mut connect = false;
+        for relay in next.keys().filter(|relay| !configured.contains(relay) && !active.contains_key(*relay)) {
+            match client.add_read_relay(relay.as_str()).await {
+                Ok(_) => connect = true,
+                Err(e) => println!("⚠️ Failed to add outbox relay {}: {}", relay, e),
+            }
+        }
+        if connect {
+            client.connect().await;
+        }
+
+        let one_hour_ago = Timestamp::now().as_u64() - 3600;
+        for (relay, authors) in next {
+            if active.get(relay) == Some(authors) {
+                continue;
+            }
+            let pubkeys: Vec<PublicKey> = authors
+                .iter()
+                .filter_map(|author| PublicKey::from_hex(author).ok())
+                .collect();
+            let filter = Filter::new()
+                .kinds(vec![
+                    Kind::ChannelMessage,
+                    Kind::TextNote,
+                    Kind::Repost,
+                    Kind::GenericRepost,
+                    Kind::EventDeletion,
+                ])
+                .authors(pubkeys)
+                .since(Timestamp::from(one_hour_ago))
+                .limit(50);
+            let id = SubscriptionId::new(format!("{}{}", OUTBOX_SUBSCRIPTION_PREFIX, relay));
+            if let Err(e) = client.subscribe_with_id_to([relay.as_str()], id, filter, None).await {
+                println!("⚠️ Failed to subscribe on {}: {}", relay, e);
+            }
+        }
+    }
+
+    /// メッセージ送信（content_warning を指定すると NIP-36 の警告タグを付与）
     pub async fn send_message(
         &self,
         content: &str,
+        content_warning: Option<String>,
     ) -> Result<EventId, Box<dyn std::error::Error + Send + Sync>> {
         let client_guard = self.client.read().await;
         let client = client_guard.as_ref().ok_or("Client not initialized")?;
 
+        // 書き込み先リレーの制限を超えるものは送る前に止める
+        self.check_relay_limits(content, usize::from(content_warning.is_some())).await?;
+
         // テキストノート（kind:1）として投稿
-        let builder = EventBuilder::text_note(content);
+        let mut builder = EventBuilder::text_note(content);
+        if let Some(reason) = content_warning {
+            builder = builder.tag(Tag::parse(["content-warning".to_string(), reason])?);
+        }
+        let output = client.send_event_builder(builder).await?;
+
+        Ok(*output.id())
+    }
+
+    /// リポスト（kind:1 は kind:6、それ以外は kind:16）
+    pub async fn repost(
+        &self,
+        event_id: &str,
+    ) -> Result<EventId, Box<dyn std::error::Error + Send + Sync>> {
+        let client_guard = self.client.read().await;
+        let client = client_guard.as_ref().ok_or("Client not initialized")?;
+
+        let target_id = EventId::from_hex(event_id)?;
+        let filter = Filter::new().id(target_id).limit(1);
+        let events = client.fetch_events(filter, FETCH_TIMEOUT).await?;
+        let original = events.into_iter().next().ok_or("Event not found")?;
+
+        let mut tags = vec![Tag::event(original.id), Tag::public_key(original.pubkey)];
+        let kind = if original.kind == Kind::TextNote {
+            Kind::Repost
+        } else {
+            tags.push(Tag::parse(["k".to_string(), original.kind.as_u16().to_string()])?);
+            Kind::GenericRepost
+        };
+
+        let builder = EventBuilder::new(kind, original.as_json()).tags(tags);
+        let output = client.send_event_builder(builder).await?;
+
+        Ok(*output.id())
+    }
+
+    /// 自分のメッセージを削除（kind:5）
+    pub async fn delete_message(
+        &self,
+        event_id: &str,
+        reason: Option<String>,
+    ) -> Result<EventId, Box<dyn std::error::Error + Send + Sync>> {
+        let client_guard = self.client.read().await;
+        let client = client_guard.as_ref().ok_or("Client not initialized")?;
+        let my_pubkey = self.public_key.read().await.ok_or("Keys not initialized")?;
+
+        // 自分のイベントであることを確認
+        let target_id = EventId::from_hex(event_id)?;
+        let filter = Filter::new().id(target_id).author(my_pubkey).limit(1);
+        let events = client.fetch_events(filter, FETCH_TIMEOUT).await?;
+        let target = events.into_iter().next().ok_or("Event not found or not owned by you")?;
+
+        let tags = vec![
+            Tag::event(target.id),
+            Tag::parse(["k".to_string(), target.kind.as_u16().to_string()])?,
+        ];
+        let builder = EventBuilder::new(Kind::EventDeletion, reason.unwrap_or_default()).tags(tags);
         let output = client.send_event_builder(builder).await?;
 
-        Ok(output.id().clone())
+        Ok(*output.id())
+    }
+
+    /// ギフトラップを開封してDMを取り出す（kind:14 以外は無視）
+    async fn unwrap_direct_message(
+        client: &Client,
+        gift_wrap: &Event,
+        my_pubkey: &PublicKey,
+    ) -> Option<DirectMessage> {
+        let UnwrappedGift { sender, rumor } = client.unwrap_gift_wrap(gift_wrap).await.ok()?;
+        if rumor.kind != Kind::PrivateDirectMessage {
+            return None;
+        }
+
+        let outgoing = sender == *my_pubkey;
+        let peer = if outgoing {
+            // 自分宛てに保存したコピーは p タグが会話相手（get_dm_history と同じ hex 形式にそろえる）
+            rumor.tags.iter().find_map(|tag| {
+                let tag_vec = tag.clone().to_vec();
+                if tag_vec.len() >= 2 && tag_vec[0] == "p" {
+                    PublicKey::from_hex(&tag_vec[1]).ok().map(|pubkey| pubkey.to_hex())
+                } else {
+                    None
+                }
+            })?
+        } else {
+            sender.to_hex()
+        };
+
+        Some(DirectMessage {
+            id: rumor.id.map(|id| id.to_hex()).unwrap_or_else(|| gift_wrap.id.to_hex()),
+            peer,
+            sender: sender.to_hex(),
+            content: rumor.content.clone(),
+            timestamp: rumor.created_at.as_u64() as i64,
+            outgoing,
+            legacy: false,
+        })
+    }
+
+    /// 旧形式のDM（kind:4 / NIP-04）を復号
+    async fn decrypt_legacy_direct_message(
+        client: &Client,
+        event: &Event,
+        my_pubkey: &PublicKey,
+    ) -> Option<DirectMessage> {
+        let outgoing = event.pubkey == *my_pubkey;
+        let peer = if outgoing {
+            let peer_hex = Self::tag_values(event, "p").into_iter().next()?;
+            PublicKey::from_hex(&peer_hex).ok()?
+        } else {
+            event.pubkey
+        };
+
+        let signer = client.signer().await.ok()?;
+        let content = signer.nip04_decrypt(&peer, &event.content).await.ok()?;
+
+        Some(DirectMessage {
+            id: event.id.to_hex(),
+            peer: peer.to_hex(),
+            sender: event.pubkey.to_hex(),
+            content,
+            timestamp: event.created_at.as_u64() as i64,
+            outgoing,
+            legacy: true,
+        })
+    }
+
+    /// DMを会話に追加（新規ならtrue）
+    async fn store_direct_message(store: &DirectMessageStore, dm: DirectMessage) -> bool {
+        let mut conversations = store.write().await;
+        let messages = conversations.entry(dm.peer.clone()).or_default();
+        if messages.iter().any(|m| m.id == dm.id) {
+            return false;
+        }
+        let position = messages
+            .iter()
+            .position(|m| m.timestamp > dm.timestamp)
+            .unwrap_or(messages.len());
+        messages.insert(position, dm);
+        true
+    }
+
+    /// DMを送信（NIP-17: kind:14 を NIP-44 で封印し kind:1059 でギフトラップ）
+    /// 旧形式（NIP-04）の会話への返信もこの形式で送る
+    pub async fn send_dm(
+        &self,
+        recipient: &str,
+        content: &str,
+    ) -> Result<EventId, Box<dyn std::error::Error + Send + Sync>> {
+        let client_guard = self.client.read().await;
+        let client = client_guard.as_ref().ok_or("Client not initialized")?;
+        let my_pubkey = self.public_key.read().await.ok_or("Keys not initialized")?;
+        let receiver = PublicKey::parse(recipient)?;
+
+        // 相手の受信用リレー（kind:10050）を取得（読めないURLは除く）
+        let filter = Filter::new().kind(Kind::InboxRelays).author(receiver).limit(1);
+        let mut inbox_relays: Vec<String> = client
+            .fetch_events(filter, FETCH_TIMEOUT)
+            .await?
+            .into_iter()
+            .max_by_key(|e| e.created_at)
+            .map(|e| Self::tag_values(&e, "relay"))
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|relay| relay_url::normalize(relay).ok())
+            .collect();
+        let mut seen = std::collections::HashSet::new();
+        inbox_relays.retain(|relay| seen.insert(relay.clone()));
+
+        // 包んだイベントを1つ作って受信用リレーに送る
+        let signer = client.signer().await?;
+        let rumor = EventBuilder::private_msg_rumor(receiver, content).build(my_pubkey);
+        let gift_wrap = EventBuilder::gift_wrap(&signer, &receiver, rumor, []).await?;
+        let mut delivered = false;
+        for relay in &inbox_relays {
+            match Self::send_to_inbox(client, relay, &gift_wrap).await {
+                Ok(()) => delivered = true,
+                Err(e) => println!("⚠️ Inbox relay {} skipped: {}", relay, e),
+            }
+        }
+        if !delivered {
+            // 受信用リレーが未設定（またはどれにも届かない）なら自分の接続先に送る
+            client.send_event(&gift_wrap).await?;
+        }
+
+        // 自分宛てにもコピーを送り、履歴に残す
+        client
+            .gift_wrap(&my_pubkey, EventBuilder::private_msg_rumor(receiver, content), [])
+            .await?;
+
+        Ok(gift_wrap.id)
+    }
+
+    /// 受信用リレーに送る（自分の公開投稿が相手の受信用リレーに流れないよう、プールのリレーの read / write は変えない）
+    /// プールにあればそのリレーだけに送り、なければプールとは別に一時的に接続する。つながらなければエラー
+    async fn send_to_inbox(
+        client: &Client,
+        url: &str,
+        event: &Event,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Ok(relay) = client.relay(url).await {
+            if relay.status() != RelayStatus::Connected {
+                client.try_connect_relay(url, RELAY_PROBE_TIMEOUT).await?;
+            }
+            let output = client.send_event_to([url], event).await?;
+            if output.success.is_empty() {
+                return Err("Relay did not accept the message".into());
+            }
+            return Ok(());
+        }
+
+        let relay = Relay::new(RelayUrl::parse(url)?);
+        let result: Result<(), Box<dyn std::error::Error + Send + Sync>> = async {
+            relay.try_connect(RELAY_PROBE_TIMEOUT).await?;
+            relay.send_event(event).await?;
+            Ok(())
+        }
+        .await;
+        let _ = relay.disconnect();
+        result
+    }
+
+    /// 会話一覧を取得（新しい順）
+    pub async fn get_conversations(&self) -> Vec<Conversation> {
+        self.decrypt_deferred_dms().await;
+        let conversations = self.direct_messages.read().await;
+        let profiles = self.profiles.read().await;
+        let mut list: Vec<Conversation> = conversations
+            .iter()
+            .filter_map(|(peer, messages)| {
+                let last = messages.last()?;
+                let peer_name = PublicKey::from_hex(peer)
+                    .map(|pk| Self::format_author(&pk, &profiles))
+                    .unwrap_or_else(|_| peer.clone());
+                Some(Conversation {
+                    peer: peer.clone(),
+                    peer_name,
+                    last_message: last.content.clone(),
+                    last_timestamp: last.timestamp,
+                    message_count: messages.len(),
+                })
+            })
+            .collect();
+        list.sort_by(|a, b| b.last_timestamp.cmp(&a.last_timestamp));
+        list
+    }
+
+    /// 会話の履歴を取得（古い順）
+    pub async fn get_dm_history(&self, peer: &str) -> Result<Vec<DirectMessage>, Box<dyn std::error::Error + Send + Sync>> {
+        let peer_hex = PublicKey::parse(peer)?.to_hex();
+        self.decrypt_deferred_dms().await;
+        Ok(self
+            .direct_messages
+            .read()
+            .await
+            .get(&peer_hex)
+            .cloned()
+            .unwrap_or_default())
     }
 
     /// 公開鍵を取得（表示用）
     pub async fn get_public_key(&self) -> Option<String> {
-        let keys_guard = self.keys.read().await;
-        keys_guard.as_ref().map(|k| k.public_key().to_bech32().unwrap_or_default())
+        self.public_key.read().await.map(|pk| pk.to_bech32().unwrap_or_default())
+    }
+
+    /// 公開鍵を取得（hex形式、受信したメッセージが自分のものか見分ける用）
+    pub async fn get_public_key_hex(&self) -> Option<String> {
+        self.public_key.read().await.map(|pk| pk.to_hex())
     }
 
     /// 秘密鍵を取得（エクスポート用、nsec形式）
@@ -529,13 +3526,29 @@ impl NostrState {
 
     /// 自分のプロフィールを取得
     pub async fn get_my_profile(&self) -> Option<Profile> {
-        let keys_guard = self.keys.read().await;
-        let pubkey_hex = keys_guard.as_ref()?.public_key().to_hex();
+        let pubkey_hex = self.public_key.read().await.as_ref()?.to_hex();
         let profiles = self.profiles.read().await;
         profiles.get(&pubkey_hex).cloned()
     }
 
+    /// 指定したユーザーのプロフィールを取得（npub または hex、キャッシュになければリレーに問い合わせる）
+    pub async fn get_profile(&self, pubkey: &str) -> Result<Option<Profile>, Box<dyn std::error::Error + Send + Sync>> {
+        let pubkey = PublicKey::parse(pubkey.trim())?;
+        let pubkey_hex = pubkey.to_hex();
+        if let Some(profile) = self.profiles.read().await.get(&pubkey_hex) {
+            return Ok(Some(profile.clone()));
+        }
+
+        let client = self.client.read().await.clone().ok_or("Client not initialized")?;
+        Self::fetch_profile_batch(&client, &self.profiles, &self.event_sender, &self.author_checks(), &[pubkey]).await?;
+        Ok(self.profiles.read().await.get(&pubkey_hex).cloned())
+    }
+
     /// プロフィールを更新（kind:0）
+    /// 最新の kind:0 に変更したフィールドだけを上書きし、他のクライアントが設定した項目は残す
+    /// None のフィールドは変更なし、clear に挙げたフィールドは削除する
+    /// 今のプロフィールが見つからなければ、new_profile で新しく作ると確認されたときだけ空から作る
+    #[allow(clippy::too_many_arguments)]
     pub async fn update_profile(
         &self,
         name: Option<String>,
@@ -544,65 +3557,253 @@ impl NostrState {
         picture: Option<String>,
         website: Option<String>,
         nip05: Option<String>,
+        clear: Vec<String>,
+        new_profile: bool,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let client_guard = self.client.read().await;
-        let client = client_guard.as_ref().ok_or("Client not initialized")?;
+        let client = self.client.read().await.clone().ok_or("Client not initialized")?;
+        let my_pubkey = self.public_key.read().await.ok_or("Keys not initialized")?;
+        let current = self.fetch_own_metadata(&client, my_pubkey, new_profile).await?;
 
-        // メタデータを構築
-        let mut metadata = serde_json::Map::new();
-        if let Some(n) = name {
-            metadata.insert("name".to_string(), serde_json::Value::String(n));
+        // メタデータをマージ
+        let mut fields = current.fields.clone();
+        for key in &clear {
+            fields.remove(key);
         }
-        if let Some(dn) = display_name {
-            metadata.insert("display_name".to_string(), serde_json::Value::String(dn));
-        }
-        if let Some(a) = about {
-            metadata.insert("about".to_string(), serde_json::Value::String(a));
-        }
-        if let Some(p) = picture {
-            metadata.insert("picture".to_string(), serde_json::Value::String(p));
-        }
-        if let Some(w) = website {
-            metadata.insert("website".to_string(), serde_json::Value::String(w));
+        let updates = [
+            ("name", name),
+            ("display_name", display_name),
+            ("about", about),
+            ("picture", picture),
+            ("website", website),
+            ("nip05", nip05),
+        ];
+        for (key, value) in updates {
+            if let Some(value) = value {
+                fields.insert(key.to_string(), serde_json::Value::String(value));
+            }
         }
-        if let Some(n) = nip05 {
-            metadata.insert("nip05".to_string(), serde_json::Value::String(n));
+        if fields == current.fields {
+            return Ok(());
         }
 
-        let content = serde_json::to_string(&metadata)?;
+        let content = serde_json::to_string(&fields)?;
         let builder = EventBuilder::new(Kind::Metadata, content);
         client.send_event_builder(builder).await?;
 
+        // 送信した内容をキャッシュに反映
+        let created_at = Timestamp::now().as_u64();
+        let profile = Self::profile_from_metadata(&fields, created_at);
+        self.profiles.write().await.insert(my_pubkey.to_hex(), profile);
+        *self.own_metadata.write().await = Some(OwnMetadata { created_at, fields });
+
         Ok(())
     }
 
-    /// 秘密鍵をインポート（nsec または hex形式）
-    pub async fn import_key(&self, key_str: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-        let secret_key = if key_str.starts_with("nsec") {
-            SecretKey::from_bech32(key_str)?
+    /// マージ元になる自分の kind:0 を取得（未取得ならリレーに問い合わせ、取得できなければエラー）
+    /// kind:0 が1件も届かないのは、タイムアウトや接続していないリレーにしかない場合と区別できないので
+    /// new_profile で新しく作ると確認されたときだけ空から始める
+    async fn fetch_own_metadata(
+        &self,
+        client: &Client,
+        my_pubkey: PublicKey,
+        new_profile: bool,
+    ) -> Result<OwnMetadata, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(cached) = self.own_metadata.read().await.clone() {
+            return Ok(cached);
+        }
+
+        let filter = Filter::new().kind(Kind::Metadata).author(my_pubkey).limit(1);
+        let events = client
+            .fetch_events(filter, FETCH_TIMEOUT)
+            .await
+            .map_err(|e| format!("Current profile could not be fetched, refusing to overwrite it: {}", e))?;
+        if let Some(event) = events.into_iter().max_by_key(|event| event.created_at) {
+            if !Self::remember_own_metadata(&self.own_metadata, &event).await {
+                return Err("Current profile is not valid JSON, refusing to overwrite it".into());
+            }
+        } else if !new_profile {
+            return Err(NO_EXISTING_PROFILE.into());
         } else {
-            SecretKey::from_hex(key_str)?
+            let mut cached = self.own_metadata.write().await;
+            if cached.is_none() {
+                *cached = Some(OwnMetadata {
+                    created_at: 0,
+                    fields: serde_json::Map::new(),
+                });
+            }
+        }
+
+        self.own_metadata
+            .read()
+            .await
+            .clone()
+            .ok_or_else(|| "Current profile has not been fetched yet".into())
+    }
+
+    /// 自分の kind:0 をキャッシュ（新しいものだけ置き換える、JSONとして読めなければ false）
+    async fn remember_own_metadata(own_metadata: &OwnMetadataCache, event: &Event) -> bool {
+        let Ok(fields) = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&event.content) else {
+            return false;
+        };
+        let created_at = event.created_at.as_u64();
+        let mut cached = own_metadata.write().await;
+        let is_newer = match cached.as_ref() {
+            Some(current) => created_at >= current.created_at,
+            None => true,
         };
+        if is_newer {
+            *cached = Some(OwnMetadata { created_at, fields });
+        }
+        true
+    }
+
+    /// kind:0 の JSON から表示用のプロフィールを作る
+    fn profile_from_metadata(metadata: &serde_json::Map<String, serde_json::Value>, created_at: u64) -> Profile {
+        const KNOWN_FIELDS: &[&str] = &["name", "display_name", "about", "picture", "website", "nip05", "banner", "bot"];
+        let field = |key: &str| metadata.get(key).and_then(|v| v.as_str()).map(String::from);
+        // bot は真偽値のはずだが文字列で入っていることもある
+        let bot = match metadata.get("bot") {
+            Some(serde_json::Value::Bool(bot)) => *bot,
+            Some(serde_json::Value::String(bot)) => bot.eq_ignore_ascii_case("true"),
+            _ => false,
+        };
+        Profile {
+            name: field("name"),
+            display_name: field("display_name"),
+            about: field("about"),
+            picture: field("picture"),
+            website: field("website"),
+            nip05: field("nip05"),
+            banner: field("banner"),
+            bot,
+            created_at,
+            extra: metadata
+                .iter()
+                .filter(|(key, _)| !KNOWN_FIELDS.contains(&key.as_str()))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    /// kind:0 イベントからプロフィールを作る（JSONオブジェクトとして読めなければ None）
+    fn profile_from_event(event: &Event) -> Option<Profile> {
+        let metadata = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&event.content).ok()?;
+        Some(Self::profile_from_metadata(&metadata, event.created_at.as_u64()))
+    }
 
-        let keys = Keys::new(secret_key);
+    /// 秘密鍵をインポート（nsec、hex形式、または12/24語、passphrase を指定すると暗号化して保存）
+    pub async fn import_key(
+        &self,
+        key_str: &str,
+        passphrase: Option<&str>,
+    ) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let keys = Keys::new(Self::parse_secret_key(key_str)?);
         let pubkey = keys.public_key().to_bech32().unwrap_or_default();
 
         // ファイルに保存
-        if let Some(path) = Self::keys_path() {
-            if let Some(parent) = path.parent() {
-                fs::create_dir_all(parent)?;
+        let dir = self.identity_dir().await;
+        let passphrase = passphrase.filter(|p| !p.is_empty());
+        Self::write_stored_keys(dir.as_deref(), &Self::to_stored_keys(&keys, passphrase)?, passphrase)?;
+
+        // 前の鍵宛ての通知を破棄
+        {
+            let mut notifications = self.notifications.write().await;
+            notifications.clear();
+            Self::save_notifications(dir.as_deref(), &notifications)?;
+        }
+
+        self.remember_identity_pubkey(&keys.public_key()).await?;
+        self.switch_signer(IdentitySigner::Local(keys)).await?;
+
+        Ok(pubkey)
+    }
+
+    /// 秘密鍵の文字列を解釈（nsec、hex形式、または NIP-06 の12/24語）
+    fn parse_secret_key(key_str: &str) -> Result<SecretKey, Box<dyn std::error::Error + Send + Sync>> {
+        let key_str = key_str.trim();
+        let words: Vec<&str> = key_str.split_whitespace().collect();
+        if words.len() > 1 {
+            if words.len() != 12 && words.len() != 24 {
+                return Err("Seed phrase must be 12 or 24 words".into());
             }
-            let stored = StoredKeys {
-                secret_key: keys.secret_key().to_secret_hex(),
-            };
-            let data = serde_json::to_string_pretty(&stored)?;
-            fs::write(&path, data)?;
+            let phrase = words.join(" ").to_lowercase();
+            let keys = Keys::from_mnemonic(phrase, None)?;
+            return Ok(keys.secret_key().clone());
+        }
+        if key_str.starts_with("nsec") {
+            Ok(SecretKey::from_bech32(key_str)?)
+        } else {
+            Ok(SecretKey::from_hex(key_str)?)
         }
+    }
+
+    /// 使用中の署名方法を切り替え（接続中なら署名者と自分に依存する購読も差し替え）
+    async fn switch_signer(&self, signer: IdentitySigner) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let public_key = signer.public_key();
+        let pubkey = public_key.to_bech32().unwrap_or_default();
+        let pubkey_hex = public_key.to_hex();
 
         // メモリ上の鍵を更新
-        *self.keys.write().await = Some(keys);
+        *self.keys.write().await = match &signer {
+            IdentitySigner::Local(keys) => Some(keys.clone()),
+            IdentitySigner::Remote { .. } => None,
+        };
+        *self.public_key.write().await = Some(public_key);
+
+        // 前の鍵に紐づく状態を破棄
+        self.direct_messages.write().await.clear();
+        self.deferred_dms.write().await.clear();
+        self.follows.write().await.clear();
+        *self.own_metadata.write().await = None;
+        // 認証（NIP-42）は接続ごとなので、前の鍵で認証した・認証を求められた接続は張り直して新しい鍵で応じ直す
+        let reauth: Vec<String> = self
+            .relay_auth
+            .write()
+            .await
+            .drain()
+            .filter(|(_, auth)| auth.state != RelayAuthState::NotRequired)
+            .map(|(url, _)| url)
+            .collect();
+        *self.pending_relay_list.write().await = None;
+        *self.relay_list_sync.write().await = RelayListSync::default();
+
+        // 接続前なら init が新しい鍵を使う
+        let client = self.client.read().await.clone();
+        let Some(client) = client else {
+            return Ok(());
+        };
+        match signer {
+            IdentitySigner::Local(keys) => client.set_signer(keys).await,
+            IdentitySigner::Remote { signer, .. } => client.set_signer(signer).await,
+        }
+        for url in &reauth {
+            if let Err(e) = client.disconnect_relay(url.as_str()).await {
+                println!("⚠️ Failed to disconnect {} for re-authentication: {}", url, e);
+            }
+            Self::update_relay_auth(&client, &self.relay_auth, &self.event_sender, url, |auth| {
+                *auth = RelayAuth::default();
+            })
+            .await;
+            if let Err(e) = client.connect_relay(url.as_str()).await {
+                println!("⚠️ Failed to reconnect {} for re-authentication: {}", url, e);
+            }
+        }
+        self.subscribe_identity().await?;
+        if let Err(e) = self.fetch_own_lists().await {
+            println!("⚠️ Failed to fetch own profile and lists: {}", e);
+        }
 
-        Ok(pubkey)
+        println!("🔑 Identity changed to {}", pubkey);
+        self.emit(NostrEvent::IdentityChanged(IdentityChanged { pubkey, pubkey_hex }))
+            .await;
+        Ok(())
+    }
+
+    /// フロントエンドにイベントを送る（接続前は何もしない）
+    async fn emit(&self, event: NostrEvent) {
+        if let Some(tx) = self.event_sender.read().await.as_ref() {
+            let _ = tx.send(event);
+        }
     }
 }
 
@@ -611,3 +3812,81 @@ impl Default for NostrState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_relay_builder::MockRelay;
+
+    /// 署名サーバー（NIP-46）の代役：要求をすべて承認する
+    struct ApproveAll;
+
+    impl NostrConnectSignerActions for ApproveAll {
+        fn approve(&self, _public_key: &PublicKey, _req: &NostrConnectRequest) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_relay_connects_to_local_relay() {
+        let relay = MockRelay::run().await.unwrap();
+        NostrState::probe_relay(&relay.url().to_string()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn probe_relay_reports_unreachable_relay() {
+        // 空いているポートを確保してから閉じ、誰も待ち受けていないURLにする
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let result = NostrState::probe_relay(&url).await;
+        assert!(matches!(result, Err(RelayUrlError::Unreachable(ref failed, _)) if *failed == url));
+    }
+
+    #[tokio::test]
+    async fn nostrconnect_pairing_is_saved_as_bunker_uri_and_reconnects() {
+        let relay = MockRelay::run().await.unwrap();
+        let relay_url = relay.url().to_string();
+        let user = Keys::generate();
+        let remote_signer_keys = Keys::generate();
+        let app_keys = Keys::generate();
+        let uri = NostrState::nostrconnect_uri(&app_keys, &relay_url);
+        let bunker = StoredBunker {
+            uri: uri.clone(),
+            app_secret_key: app_keys.secret_key().to_secret_hex(),
+        };
+
+        // アプリ側が待ち受けてから、署名サーバーに nostrconnect:// を貼り付けて承認してもらう
+        let pairing = {
+            let bunker = bunker.clone();
+            tokio::spawn(async move { NostrState::connect_remote_signer(&bunker).await })
+        };
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let remote = NostrConnectRemoteSigner::from_uri(
+            NostrConnectURI::parse(&uri).unwrap(),
+            NostrConnectKeys {
+                signer: remote_signer_keys.clone(),
+                user: user.clone(),
+            },
+            None::<String>,
+            None,
+        )
+        .unwrap();
+        tokio::spawn(async move {
+            let _ = remote.serve(ApproveAll).await;
+        });
+
+        let signer = pairing.await.unwrap().unwrap();
+        assert_eq!(signer.public_key(), user.public_key());
+
+        let saved = NostrState::reconnectable_bunker(bunker, &signer).await.unwrap();
+        assert!(saved.uri.starts_with("bunker://"), "saved {}", saved.uri);
+        assert!(saved.uri.contains(&remote_signer_keys.public_key().to_hex()));
+        assert_eq!(saved.app_secret_key, app_keys.secret_key().to_secret_hex());
+
+        // 再起動したつもりで、保存した形から署名サーバーの応答を待たずにつなぎ直せる
+        let restarted = NostrState::connect_remote_signer(&saved).await.unwrap();
+        assert_eq!(restarted.public_key(), user.public_key());
+    }
+}
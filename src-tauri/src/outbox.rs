@@ -0,0 +1,239 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// 覚えておくスレッド参加者の上限（超えたら古いものから忘れる）
+const MAX_PARTICIPANTS: usize = 500;
+
+/// 作者が NIP-65（kind:10002）で宣言した書き込み先リレー
+struct AuthorRelays {
+    relays: Vec<String>,
+    created_at: u64, // kind:10002 が見つからなかった作者は 0
+}
+
+/// 作者ごとの書き込み先リレーと、表示したスレッドの参加者
+#[derive(Default)]
+pub struct OutboxStore {
+    write_relays: HashMap<String, AuthorRelays>, // pubkey hex → リレー
+    participants: VecDeque<String>,              // 古い順
+}
+
+/// 1つのリレーで購読する作者
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RelayAssignment {
+    pub relay: String,
+    pub authors: Vec<String>,
+}
+
+/// リレー選択の結果
+#[derive(Default, PartialEq, Eq, Debug)]
+pub struct RelaySelection {
+    pub assignments: Vec<RelayAssignment>, // 接続済みのリレーから順に
+    pub uncovered: Vec<String>,            // 書き込み先が不明か、上限を超えて拾えなかった作者
+}
+
+impl OutboxStore {
+    /// kind:10002 を問い合わせ済みか
+    pub fn contains(&self, pubkey_hex: &str) -> bool {
+        self.write_relays.contains_key(pubkey_hex)
+    }
+
+    /// 書き込み先リレーを保存（既存より新しい created_at のときだけ置き換え、置き換えたら true）
+    pub fn insert(&mut self, pubkey_hex: String, relays: Vec<String>, created_at: u64) -> bool {
+        if let Some(current) = self.write_relays.get(&pubkey_hex) {
+            if created_at <= current.created_at {
+                return false;
+            }
+        }
+        self.write_relays.insert(pubkey_hex, AuthorRelays { relays, created_at });
+        true
+    }
+
+    /// 問い合わせたが kind:10002 が見つからなかった作者として記録（何度も問い合わせない）
+    pub fn mark_missing(&mut self, pubkey_hex: &str) {
+        self.write_relays
+            .entry(pubkey_hex.to_string())
+            .or_insert_with(|| AuthorRelays {
+                relays: Vec::new(),
+                created_at: 0,
+            });
+    }
+
+    /// スレッドの参加者を記録（新しく加わったら true）
+    pub fn add_participant(&mut self, pubkey_hex: &str) -> bool {
+        if self.participants.iter().any(|p| p == pubkey_hex) {
+            return false;
+        }
+        self.participants.push_back(pubkey_hex.to_string());
+        if self.participants.len() > MAX_PARTICIPANTS {
+            self.participants.pop_front();
+        }
+        true
+    }
+
+    /// 記録しているスレッドの参加者
+    pub fn participants(&self) -> impl Iterator<Item = &String> {
+        self.participants.iter()
+    }
+
+    /// 作者の書き込み先リレーを選ぶ（貪欲法で、少ないリレーで多くの作者を拾う）
+    /// 接続済みのリレーは追加の接続なしで使えるので先に選び、それ以外は max_extra 件まで
+    pub fn select_relays(&self, authors: &[String], connected: &[String], max_extra: usize) -> RelaySelection {
+        // リレー → そのリレーに書いている作者
+        let mut candidates: HashMap<&str, HashSet<&str>> = HashMap::new();
+        let mut uncovered: HashSet<&str> = HashSet::new();
+        let mut selection = RelaySelection::default();
+        for author in authors {
+            match self.write_relays.get(author) {
+                Some(entry) if !entry.relays.is_empty() => {
+                    uncovered.insert(author.as_str());
+                    for relay in &entry.relays {
+                        candidates.entry(relay.as_str()).or_default().insert(author.as_str());
+                    }
+                }
+                _ => selection.uncovered.push(author.clone()),
+            }
+        }
+
+        let mut extra = 0;
+        for only_connected in [true, false] {
+            loop {
+                if !only_connected && extra >= max_extra {
+                    break;
+                }
+                // まだ拾えていない作者を最も多く含むリレー（同数なら URL 順で決める）
+                let best = candidates
+                    .iter()
+                    .filter(|(relay, _)| connected.iter().any(|c| c == *relay) == only_connected)
+                    .map(|(relay, writers)| (*relay, writers.iter().filter(|a| uncovered.contains(*a)).count()))
+                    .filter(|(_, count)| *count > 0)
+                    .max_by(|(a_relay, a_count), (b_relay, b_count)| a_count.cmp(b_count).then(b_relay.cmp(a_relay)));
+                let Some((relay, _)) = best else {
+                    break;
+                };
+
+                let mut covered: Vec<String> = candidates[relay]
+                    .iter()
+                    .filter(|author| uncovered.contains(*author))
+                    .map(|author| author.to_string())
+                    .collect();
+                covered.sort();
+                for author in &covered {
+                    uncovered.remove(author.as_str());
+                }
+                candidates.remove(relay);
+                selection.assignments.push(RelayAssignment {
+                    relay: relay.to_string(),
+                    authors: covered,
+                });
+                if !only_connected {
+                    extra += 1;
+                }
+            }
+        }
+
+        let mut rest: Vec<String> = uncovered.into_iter().map(str::to_string).collect();
+        rest.sort();
+        selection.uncovered.extend(rest);
+        selection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn store(entries: &[(&str, &[&str])]) -> OutboxStore {
+        let mut store = OutboxStore::default();
+        for (author, relays) in entries {
+            store.insert(author.to_string(), strings(relays), 1);
+        }
+        store
+    }
+
+    #[test]
+    fn prefers_connected_relays() {
+        let store = store(&[("alice", &["wss://a", "wss://b"]), ("bob", &["wss://b", "wss://c"])]);
+        let selection = store.select_relays(&strings(&["alice", "bob"]), &strings(&["wss://c"]), 8);
+        assert_eq!(
+            selection.assignments,
+            vec![
+                RelayAssignment {
+                    relay: "wss://c".to_string(),
+                    authors: strings(&["bob"]),
+                },
+                RelayAssignment {
+                    relay: "wss://a".to_string(),
+                    authors: strings(&["alice"]),
+                },
+            ]
+        );
+        assert!(selection.uncovered.is_empty());
+    }
+
+    #[test]
+    fn covers_most_authors_with_fewest_relays() {
+        let store = store(&[
+            ("alice", &["wss://a", "wss://shared"]),
+            ("bob", &["wss://b", "wss://shared"]),
+            ("carol", &["wss://shared"]),
+        ]);
+        let selection = store.select_relays(&strings(&["alice", "bob", "carol"]), &[], 8);
+        assert_eq!(
+            selection.assignments,
+            vec![RelayAssignment {
+                relay: "wss://shared".to_string(),
+                authors: strings(&["alice", "bob", "carol"]),
+            }]
+        );
+    }
+
+    #[test]
+    fn breaks_ties_by_relay_url() {
+        let store = store(&[("alice", &["wss://b", "wss://a"])]);
+        let selection = store.select_relays(&strings(&["alice"]), &[], 8);
+        assert_eq!(selection.assignments[0].relay, "wss://a");
+    }
+
+    #[test]
+    fn limits_extra_relays_and_reports_the_rest_as_uncovered() {
+        let store = store(&[("alice", &["wss://a"]), ("bob", &["wss://b"]), ("carol", &["wss://c"])]);
+        let selection = store.select_relays(&strings(&["alice", "bob", "carol"]), &strings(&["wss://c"]), 1);
+        let relays: Vec<&str> = selection.assignments.iter().map(|a| a.relay.as_str()).collect();
+        assert_eq!(relays, vec!["wss://c", "wss://a"]);
+        assert_eq!(selection.uncovered, strings(&["bob"]));
+    }
+
+    #[test]
+    fn authors_without_relay_list_are_uncovered() {
+        let mut store = store(&[("alice", &["wss://a"])]);
+        store.mark_missing("bob");
+        let selection = store.select_relays(&strings(&["alice", "bob", "carol"]), &[], 8);
+        assert_eq!(selection.assignments.len(), 1);
+        assert_eq!(selection.uncovered, strings(&["bob", "carol"]));
+    }
+
+    #[test]
+    fn insert_keeps_the_newest_relay_list() {
+        let mut store = OutboxStore::default();
+        assert!(store.insert("alice".to_string(), strings(&["wss://new"]), 20));
+        assert!(!store.insert("alice".to_string(), strings(&["wss://old"]), 10));
+        store.mark_missing("alice");
+        let selection = store.select_relays(&strings(&["alice"]), &[], 8);
+        assert_eq!(selection.assignments[0].relay, "wss://new");
+    }
+
+    #[test]
+    fn participants_are_capped_and_deduplicated() {
+        let mut store = OutboxStore::default();
+        assert!(store.add_participant("first"));
+        assert!(!store.add_participant("first"));
+        for i in 0..MAX_PARTICIPANTS {
+            store.add_participant(&format!("p{}", i));
+        }
+        assert_eq!(store.participants().count(), MAX_PARTICIPANTS);
+        assert!(!store.participants().any(|p| p == "first"));
+    }
+}
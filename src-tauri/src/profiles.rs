@@ -0,0 +1,212 @@
+use crate::nostr_client::Profile;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// キャッシュに残すプロフィールの上限（超えたら最後に使われたのが古いものから捨てる）
+const DEFAULT_CAPACITY: usize = 5000;
+
+/// キャッシュされたプロフィール1件
+#[derive(Serialize, Deserialize)]
+struct CachedProfile {
+    profile: Profile, // created_at が新しいものだけで置き換える
+    fetched_at: u64, // 最後にリレーに問い合わせた時刻（秒）
+    #[serde(default)]
+    last_used: AtomicU64, // 最後に表示に使った時刻（ミリ秒、読み取りロック中でも更新する）
+}
+
+/// プロフィールキャッシュ（pubkey hex → Profile）
+/// ディスクに保存して再起動後も使い、古くなったものはバックグラウンドで取り直す
+#[derive(Serialize, Deserialize)]
+pub struct ProfileStore {
+    entries: HashMap<String, CachedProfile>,
+    #[serde(skip, default = "default_capacity")]
+    capacity: usize,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+fn default_capacity() -> usize {
+    DEFAULT_CAPACITY
+}
+
+impl Default for ProfileStore {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            capacity: DEFAULT_CAPACITY,
+            dirty: false,
+        }
+    }
+}
+
+impl ProfileStore {
+    /// プロフィールを取得（LRU の順番を更新する）
+    pub fn get(&self, pubkey_hex: &str) -> Option<&Profile> {
+        let entry = self.entries.get(pubkey_hex)?;
+        entry.last_used.store(now_millis(), Ordering::Relaxed);
+        Some(&entry.profile)
+    }
+
+    /// キャッシュにあるか（LRU の順番は変えない）
+    pub fn contains_key(&self, pubkey_hex: &str) -> bool {
+        self.entries.contains_key(pubkey_hex)
+    }
+
+    /// kind:0 の内容を保存（既存より新しい created_at のときだけ置き換え、置き換えたら true）
+    pub fn insert(&mut self, pubkey_hex: String, profile: Profile) -> bool {
+        let now = now_millis();
+        if let Some(entry) = self.entries.get_mut(&pubkey_hex) {
+            entry.fetched_at = now / 1000;
+            self.dirty = true;
+            if profile.created_at <= entry.profile.created_at {
+                return false;
+            }
+            entry.profile = profile;
+            entry.last_used.store(now, Ordering::Relaxed);
+            return true;
+        }
+
+        self.entries.insert(
+            pubkey_hex,
+            CachedProfile {
+                profile,
+                fetched_at: now / 1000,
+                last_used: AtomicU64::new(now),
+            },
+        );
+        self.dirty = true;
+        self.evict();
+        true
+    }
+
+    /// 問い合わせ済みとして記録（kind:0 が見つからなくても、しばらく取り直さない）
+    pub fn mark_fetched(&mut self, pubkey_hexes: &[String]) {
+        let now = now_millis() / 1000;
+        for pubkey_hex in pubkey_hexes {
+            if let Some(entry) = self.entries.get_mut(pubkey_hex) {
+                entry.fetched_at = now;
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// 最後の問い合わせから max_age_secs 以上たったもの（古い順に最大 limit 件）
+    pub fn stale(&self, max_age_secs: u64, limit: usize) -> Vec<String> {
+        let threshold = (now_millis() / 1000).saturating_sub(max_age_secs);
+        let mut stale: Vec<(&String, u64)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.fetched_at < threshold)
+            .map(|(pubkey_hex, entry)| (pubkey_hex, entry.fetched_at))
+            .collect();
+        stale.sort_by_key(|(_, fetched_at)| *fetched_at);
+        stale.into_iter().take(limit).map(|(pubkey_hex, _)| pubkey_hex.clone()).collect()
+    }
+
+    /// 保存していない変更があれば true を返し、フラグを下ろす
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// 上限を超えた分を、最後に使われたのが古いものから捨てる
+    fn evict(&mut self) {
+        if self.entries.len() <= self.capacity {
+            return;
+        }
+        let mut by_last_used: Vec<(String, u64)> = self
+            .entries
+            .iter()
+            .map(|(pubkey_hex, entry)| (pubkey_hex.clone(), entry.last_used.load(Ordering::Relaxed)))
+            .collect();
+        by_last_used.sort_by_key(|(_, last_used)| *last_used);
+        let excess = self.entries.len() - self.capacity;
+        for (pubkey_hex, _) in by_last_used.into_iter().take(excess) {
+            self.entries.remove(&pubkey_hex);
+        }
+    }
+}
+
+/// 現在時刻（ミリ秒）
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis().max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str, created_at: u64) -> Profile {
+        Profile {
+            name: Some(name.to_string()),
+            created_at,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn older_created_at_is_ignored() {
+        let mut store = ProfileStore::default();
+        assert!(store.insert("alice".to_string(), profile("new", 20)));
+        assert!(!store.insert("alice".to_string(), profile("old", 10)));
+        assert!(!store.insert("alice".to_string(), profile("same", 20)));
+        assert_eq!(store.get("alice").and_then(|p| p.name.as_deref()), Some("new"));
+        assert!(store.insert("alice".to_string(), profile("newer", 30)));
+        assert_eq!(store.get("alice").and_then(|p| p.name.as_deref()), Some("newer"));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_beyond_capacity() {
+        let mut store = ProfileStore {
+            capacity: 2,
+            ..Default::default()
+        };
+        store.insert("alice".to_string(), profile("alice", 1));
+        store.insert("bob".to_string(), profile("bob", 1));
+        // bob より alice の方が最近使われた
+        store.entries["alice"].last_used.store(2, Ordering::Relaxed);
+        store.entries["bob"].last_used.store(1, Ordering::Relaxed);
+
+        store.insert("carol".to_string(), profile("carol", 1));
+        assert!(store.contains_key("alice"));
+        assert!(!store.contains_key("bob"));
+        assert!(store.contains_key("carol"));
+    }
+
+    #[test]
+    fn get_refreshes_last_used() {
+        let mut store = ProfileStore::default();
+        store.insert("alice".to_string(), profile("alice", 1));
+        store.entries["alice"].last_used.store(0, Ordering::Relaxed);
+        store.get("alice");
+        assert!(store.entries["alice"].last_used.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn stale_returns_oldest_fetches_first() {
+        let mut store = ProfileStore::default();
+        for name in ["alice", "bob", "carol"] {
+            store.insert(name.to_string(), profile(name, 1));
+        }
+        store.entries.get_mut("alice").unwrap().fetched_at = 100;
+        store.entries.get_mut("bob").unwrap().fetched_at = 50;
+        // carol は今取得したばかり
+
+        assert_eq!(store.stale(3600, 10), vec!["bob".to_string(), "alice".to_string()]);
+        assert_eq!(store.stale(3600, 1), vec!["bob".to_string()]);
+
+        store.mark_fetched(&["bob".to_string()]);
+        assert_eq!(store.stale(3600, 10), vec!["alice".to_string()]);
+    }
+
+    #[test]
+    fn changes_mark_the_store_dirty_once() {
+        let mut store = ProfileStore::default();
+        assert!(!store.take_dirty());
+        store.insert("alice".to_string(), profile("alice", 1));
+        assert!(store.take_dirty());
+        assert!(!store.take_dirty());
+        store.mark_fetched(&["alice".to_string()]);
+        assert!(store.take_dirty());
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt;
+use url::Url;
+
+/// リレーURLが使えない理由
+#[derive(Debug)]
+pub enum RelayUrlError {
+    Invalid(String),           // URLとして読めない
+    UnsupportedScheme(String), // ws / wss 以外
+    MissingHost,
+    Duplicate(String),           // 正規化すると登録済みのリレーと同じ
+    Unreachable(String, String), // 接続確認に失敗（URL, 理由）
+}
+
+impl fmt::Display for RelayUrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(reason) => write!(f, "Invalid relay URL: {}", reason),
+            Self::UnsupportedScheme(scheme) => write!(f, "Relay URL must use ws:// or wss:// (got {}://)", scheme),
+            Self::MissingHost => write!(f, "Relay URL has no host"),
+            Self::Duplicate(url) => write!(f, "Relay already added: {}", url),
+            Self::Unreachable(url, reason) => write!(f, "Could not connect to {}: {}", url, reason),
+        }
+    }
+}
+
+impl std::error::Error for RelayUrlError {}
+
+/// リレーURLを正規化（スキームがなければ wss://、ホストは小文字、既定のポートと末尾の / は除く）
+pub fn normalize(input: &str) -> Result<String, RelayUrlError> {
+    let input = input.trim();
+    let with_scheme = if input.contains("://") {
+        input.to_string()
+    } else {
+        format!("wss://{}", input)
+    };
+    let url = Url::parse(&with_scheme).map_err(|e| RelayUrlError::Invalid(e.to_string()))?;
+    if url.scheme() != "ws" && url.scheme() != "wss" {
+        return Err(RelayUrlError::UnsupportedScheme(url.scheme().to_string()));
+    }
+    let host = url.host_str().filter(|host| !host.is_empty()).ok_or(RelayUrlError::MissingHost)?;
+
+    // ws / wss は特別なスキームなので、ホストの小文字化と既定ポートの省略は url が済ませている
+    let mut normalized = format!("{}://{}", url.scheme(), host.to_lowercase());
+    if let Some(port) = url.port() {
+        normalized.push_str(&format!(":{}", port));
+    }
+    normalized.push_str(url.path().trim_end_matches('/'));
+    if let Some(query) = url.query() {
+        normalized.push('?');
+        normalized.push_str(query);
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_wss_to_bare_hostname() {
+        assert_eq!(normalize("relay.damus.io").unwrap(), "wss://relay.damus.io");
+        assert_eq!(normalize("  nos.lol  ").unwrap(), "wss://nos.lol");
+    }
+
+    #[test]
+    fn strips_trailing_slash() {
+        assert_eq!(normalize("wss://relay.damus.io/").unwrap(), "wss://relay.damus.io");
+        assert_eq!(normalize("wss://nostr.example.com/inbox/").unwrap(), "wss://nostr.example.com/inbox");
+    }
+
+    #[test]
+    fn lowercases_host_and_scheme() {
+        assert_eq!(normalize("WSS://Relay.Damus.IO").unwrap(), "wss://relay.damus.io");
+    }
+
+    #[test]
+    fn keeps_explicit_port_and_query_but_drops_default_port() {
+        assert_eq!(normalize("ws://localhost:7777").unwrap(), "ws://localhost:7777");
+        assert_eq!(normalize("wss://relay.example.com:443/").unwrap(), "wss://relay.example.com");
+        assert_eq!(normalize("wss://relay.example.com/?key=1").unwrap(), "wss://relay.example.com?key=1");
+    }
+
+    #[test]
+    fn rejects_non_websocket_schemes() {
+        assert!(matches!(normalize("https://relay.damus.io"), Err(RelayUrlError::UnsupportedScheme(scheme)) if scheme == "https"));
+        assert!(matches!(normalize("http://relay.damus.io"), Err(RelayUrlError::UnsupportedScheme(_))));
+    }
+
+    #[test]
+    fn rejects_urls_without_host() {
+        assert!(matches!(
+            normalize("wss://"),
+            Err(RelayUrlError::Invalid(_) | RelayUrlError::MissingHost)
+        ));
+        assert!(normalize("").is_err());
+    }
+
+    #[test]
+    fn spellings_of_the_same_relay_normalize_to_one_url() {
+        let spellings = ["relay.damus.io", "wss://RELAY.damus.io/", " wss://relay.damus.io:443 "];
+        for spelling in spellings {
+            assert_eq!(normalize(spelling).unwrap(), "wss://relay.damus.io", "{}", spelling);
+        }
+        assert_ne!(normalize("ws://relay.damus.io").unwrap(), "wss://relay.damus.io");
+    }
+}
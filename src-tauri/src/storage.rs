@@ -0,0 +1,149 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// 鍵ファイルのバックアップを残す世代数
+const KEY_BACKUP_GENERATIONS: usize = 3;
+
+/// 設定ファイルを安全に書き込む
+/// 一時ファイルに書いて fsync し、アトミックに置き換える。Unix では所有者のみ読み書き可能にする
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no parent directory"))?;
+    fs::create_dir_all(parent)?;
+
+    let tmp_path = temp_path(path);
+    let result = (|| {
+        let mut file = open_private(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)?;
+        sync_dir(parent)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// 直前の内容をバックアップしてから書き込む（keys.json.bak.1 が最新）
+/// backup_form で既存のバックアップと直前の内容をバックアップとして残す形に変換し、None なら残さず消去する
+/// （平文の秘密鍵を暗号化したあとなどに、古い平文をバックアップに残さないため）
+pub fn write_with_backup(path: &Path, data: &[u8], backup_form: impl Fn(&[u8]) -> Option<Vec<u8>>) -> io::Result<()> {
+    // 既存のバックアップを変換（残せないものは消去）
+    for generation in 1..=KEY_BACKUP_GENERATIONS {
+        let backup = backup_path(path, generation);
+        if !backup.exists() {
+            continue;
+        }
+        let current = fs::read(&backup)?;
+        match backup_form(&current) {
+            Some(converted) if converted == current => {}
+            Some(converted) => {
+                wipe(&backup)?;
+                write_atomic(&backup, &converted)?;
+            }
+            None => remove_securely(&backup)?,
+        }
+    }
+
+    if !path.exists() {
+        return write_atomic(path, data);
+    }
+    let current = fs::read(path)?;
+    let converted = backup_form(&current);
+    if let Some(converted) = &converted {
+        rotate_backups(path, converted)?;
+    }
+    if converted.as_deref() == Some(current.as_slice()) {
+        return write_atomic(path, data);
+    }
+
+    // 置き換える前の内容はそのまま残せないので、置き換えたあとで元のファイルの中身を消す
+    let old = OpenOptions::new().write(true).open(path)?;
+    write_atomic(path, data)?;
+    wipe_file(&old, current.len())
+}
+
+/// バックアップを1世代ずつずらし、直前の内容を .bak.1 に書く
+fn rotate_backups(path: &Path, previous: &[u8]) -> io::Result<()> {
+    for generation in (1..KEY_BACKUP_GENERATIONS).rev() {
+        let from = backup_path(path, generation);
+        if from.exists() {
+            fs::rename(&from, backup_path(path, generation + 1))?;
+        }
+    }
+    write_atomic(&backup_path(path, 1), previous)
+}
+
+/// ファイルの中身をゼロで上書きしてから削除
+fn remove_securely(path: &Path) -> io::Result<()> {
+    wipe(path)?;
+    fs::remove_file(path)?;
+    sync_dir(path.parent().unwrap_or(Path::new(".")))
+}
+
+/// ファイルの中身をゼロで上書き
+fn wipe(path: &Path) -> io::Result<()> {
+    let len = fs::metadata(path)?.len() as usize;
+    let file = OpenOptions::new().write(true).open(path)?;
+    wipe_file(&file, len)
+}
+
+/// 開いているファイルの先頭から len バイトをゼロで上書きして fsync
+fn wipe_file(mut file: &File, len: usize) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&vec![0u8; len])?;
+    file.sync_all()
+}
+
+/// バックアップファイルのパス（keys.json → keys.json.bak.N）
+fn backup_path(path: &Path, generation: usize) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".bak.{}", generation));
+    path.with_file_name(name)
+}
+
+/// 一時ファイルのパス（同じディレクトリに置いて rename をアトミックにする）
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// 所有者のみ読み書き可能なファイルとして開く
+#[cfg(unix)]
+fn open_private(path: &Path) -> io::Result<File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // 既存の一時ファイルが残っていた場合にも権限を揃える
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).truncate(true).open(path)
+}
+
+/// rename をディスクに反映させるためディレクトリも fsync
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
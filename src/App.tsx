@@ -4,6 +4,54 @@ import { listen, UnlistenFn } from "@tauri-apps/api/event";
 import Settings from "./Settings";
 import "./App.css";
 
+type ContentSegment =
+  | { type: "text"; text: string }
+  | { type: "url"; url: string }
+  | { type: "mention_profile"; pubkey: string; name: string }
+  | { type: "mention_event"; id: string; author: string | null; relays: string[] }
+  | { type: "hashtag"; tag: string }
+  | { type: "custom_emoji"; shortcode: string; url: string };
+
+interface AuthorUpdated {
+  pubkey: string;
+  author: string;
+  look_alike_name: boolean;
+  hidden_bot: boolean;
+}
+
+interface Nip05Verified {
+  pubkey: string;
+  nip05: string;
+}
+
+interface RelayListOffer {
+  relays: { url: string; read: boolean; write: boolean }[];
+}
+
+interface RelayStatus {
+  url: string;
+  connection: string;
+  auth: "not_required" | "awaiting_approval" | "authenticating" | "authenticated" | "declined" | "failed";
+  message: string | null;
+}
+
+interface IdentityChanged {
+  pubkey: string;
+  pubkey_hex: string;
+}
+
+interface IdentitySwitchFailed {
+  id: string;
+  name: string;
+  locked: boolean;
+  message: string;
+}
+
+interface DeletedMessage {
+  id: string;
+  reason?: string;
+}
+
 interface Message {
   id: string;
   pubkey: string;
@@ -12,6 +60,13 @@ interface Message {
   timestamp: number;
   is_post: boolean;
   context?: string;
+  reposted_by?: string;
+  repost_count?: number;
+  content_warning?: string | null;
+  segments?: ContentSegment[];
+  verified_nip05?: string | null;
+  look_alike_name?: boolean;
+  short_npub?: string;
 }
 
 // タイムスタンプをフォーマット
@@ -36,28 +91,41 @@ function formatTime(timestamp: number): string {
   return date.toLocaleDateString("ja-JP", { month: "short", day: "numeric" });
 }
 
-// URLをリンクに変換
-function linkify(text: string): React.ReactNode[] {
-  const urlRegex = /(https?:\/\/[^\s]+)/g;
-  const parts = text.split(urlRegex);
+// URLをドメインのみの表示に
+function displayHost(url: string): string {
+  try {
+    return new URL(url).hostname;
+  } catch {
+    // パースに失敗した場合はそのまま表示
+    return url;
+  }
+}
 
-  return parts.map((part, i) => {
-    if (urlRegex.test(part)) {
-      // URLの場合、リンクとして表示（ドメインのみ表示）
-      let displayUrl = part;
-      try {
-        const url = new URL(part);
-        displayUrl = url.hostname;
-      } catch {
-        // パースに失敗した場合はそのまま表示
-      }
-      return (
-        <a key={i} href={part} target="_blank" rel="noopener noreferrer">
-          {displayUrl}
-        </a>
-      );
+// 本文セグメントを描画（セグメントがなければテキストのまま）
+function renderContent(msg: Message): React.ReactNode[] {
+  if (!msg.segments || msg.segments.length === 0) {
+    return [<span key={0}>{msg.content}</span>];
+  }
+
+  return msg.segments.map((seg, i) => {
+    switch (seg.type) {
+      case "url":
+        return (
+          <a key={i} href={seg.url} target="_blank" rel="noopener noreferrer">
+            {displayHost(seg.url)}
+          </a>
+        );
+      case "mention_profile":
+        return <span key={i} className="mention">@{seg.name}</span>;
+      case "mention_event":
+        return <span key={i} className="mention">📝引用</span>;
+      case "hashtag":
+        return <span key={i} className="hashtag">#{seg.tag}</span>;
+      case "custom_emoji":
+        return <img key={i} className="custom-emoji" src={seg.url} alt={`:${seg.shortcode}:`} />;
+      default:
+        return <span key={i}>{seg.text}</span>;
     }
-    return <span key={i}>{part}</span>;
   });
 }
 
@@ -65,17 +133,30 @@ function App() {
   const [messages, setMessages] = useState<Message[]>([]);
   const [input, setInput] = useState("");
   const [status, setStatus] = useState<"connecting" | "connected" | "error">("connecting");
+  const [locked, setLocked] = useState(false);
+  const [passphrase, setPassphrase] = useState("");
   const [showSettings, setShowSettings] = useState(false);
-  const [contextMenu, setContextMenu] = useState<{ x: number; y: number; pubkey: string; author: string } | null>(null);
+  const [contextMenu, setContextMenu] = useState<{ x: number; y: number; id: string; pubkey: string; author: string } | null>(null);
+  const [unread, setUnread] = useState(0);
+  const [revealed, setRevealed] = useState<Set<string>>(new Set());
+  const [contentWarningMode, setContentWarningMode] = useState<"hide" | "blur" | "show">("blur");
+  const [relayOffer, setRelayOffer] = useState<RelayListOffer | null>(null);
+  const [authRequests, setAuthRequests] = useState<string[]>([]);
+  const [switchFailure, setSwitchFailure] = useState<IdentitySwitchFailed | null>(null);
+  const [switchPassphrase, setSwitchPassphrase] = useState("");
+  const [myPubkey, setMyPubkey] = useState<string | null>(null);
   const messagesEndRef = useRef<HTMLDivElement>(null);
   const isConnectedRef = useRef(false);
 
   // メッセージ追加（重複除去）
   const addMessage = useCallback((msg: Message) => {
     setMessages((prev) => {
-      // 重複チェック
+      // 重複チェック（リポストで集計が更新された場合は差し替え）
       if (prev.some((m) => m.id === msg.id)) {
-        return prev;
+        if (!msg.reposted_by) {
+          return prev;
+        }
+        return prev.map((m) => (m.id === msg.id ? msg : m));
       }
       // 時系列順に挿入
       const updated = [...prev, msg].sort((a, b) => a.timestamp - b.timestamp);
@@ -87,6 +168,14 @@ function App() {
   // Nostr接続とイベントリスニング
   useEffect(() => {
     let unlisten: UnlistenFn | null = null;
+    let unlistenDeleted: UnlistenFn | null = null;
+    let unlistenNotification: UnlistenFn | null = null;
+    let unlistenAuthor: UnlistenFn | null = null;
+    let unlistenNip05: UnlistenFn | null = null;
+    let unlistenRelayList: UnlistenFn | null = null;
+    let unlistenRelayStatus: UnlistenFn | null = null;
+    let unlistenSwitchFailed: UnlistenFn | null = null;
+    let unlistenIdentity: UnlistenFn | null = null;
 
     const init = async () => {
       // 重複実行を防ぐ（React StrictMode対策）
@@ -94,14 +183,75 @@ function App() {
       isConnectedRef.current = true;
 
       try {
+        // 鍵がパスフレーズで保護されていればロック解除を待つ
+        const keyStatus = await invoke<{ encrypted: boolean; unlocked: boolean }>("get_key_status");
+        if (keyStatus.encrypted && !keyStatus.unlocked) {
+          setLocked(true);
+          isConnectedRef.current = false;
+          return;
+        }
+
         // イベントリスナー登録
         unlisten = await listen<Message>("nostr-message", (event) => {
           addMessage(event.payload);
         });
+        unlistenDeleted = await listen<DeletedMessage>("message-deleted", (event) => {
+          setMessages((prev) => prev.filter((m) => m.id !== event.payload.id));
+        });
+        unlistenNotification = await listen<{ unread: number }>("notification", (event) => {
+          setUnread(event.payload.unread);
+        });
+        // 後から届いたプロフィールで表示済みの名前を差し替え
+        unlistenAuthor = await listen<AuthorUpdated>("author-updated", (event) => {
+          const { pubkey, author, look_alike_name, hidden_bot } = event.payload;
+          // bot を非表示にする設定で bot だと分かったら、表示済みのメッセージも消す
+          if (hidden_bot) {
+            setMessages((prev) => prev.filter((m) => m.pubkey !== pubkey));
+            return;
+          }
+          setMessages((prev) =>
+            prev.map((m) => ({
+              ...m,
+              author: m.pubkey === pubkey ? author : m.author,
+              look_alike_name: m.pubkey === pubkey ? look_alike_name : m.look_alike_name,
+              segments: m.segments?.map((segment) =>
+                segment.type === "mention_profile" && segment.pubkey === pubkey
+                  ? { ...segment, name: author }
+                  : segment
+              ),
+            }))
+          );
+        });
+        unlistenNip05 = await listen<Nip05Verified>("nip05-verified", (event) => {
+          const { pubkey, nip05 } = event.payload;
+          setMessages((prev) => prev.map((m) => (m.pubkey === pubkey ? { ...m, verified_nip05: nip05 } : m)));
+        });
+        // 公開済みのリレーリストにローカルにないリレーがあれば追加するか尋ねる
+        unlistenRelayList = await listen<RelayListOffer>("relay-list-found", (event) => {
+          setRelayOffer(event.payload);
+        });
+        // 認証（NIP-42）を求めてきたリレーのうち、応じるか確認が必要なもの
+        unlistenRelayStatus = await listen<RelayStatus>("relay-status", (event) => {
+          const { url, auth } = event.payload;
+          setAuthRequests((prev) => {
+            const others = prev.filter((u) => u !== url);
+            return auth === "awaiting_approval" ? [...others, url] : others;
+          });
+        });
+        // トレイメニューからの切り替えに失敗した（パスフレーズが要るならここで尋ねる）
+        unlistenSwitchFailed = await listen<IdentitySwitchFailed>("identity-switch-failed", (event) => {
+          setSwitchFailure(event.payload);
+        });
+        // 自分のメッセージの見分けに使う公開鍵を切り替え
+        unlistenIdentity = await listen<IdentityChanged>("identity-changed", (event) => {
+          setMyPubkey(event.payload.pubkey_hex);
+        });
 
         // Nostrに接続
         await invoke<string>("connect");
         setStatus("connected");
+        invoke<string>("get_public_key_hex").then(setMyPubkey).catch(console.error);
+        invoke<number>("get_unread_count").then(setUnread).catch(console.error);
 
         // 初期メッセージを取得（ダミー）
         const msgs = await invoke<Message[]>("get_messages");
@@ -118,8 +268,40 @@ function App() {
       if (unlisten) {
         unlisten();
       }
+      if (unlistenDeleted) {
+        unlistenDeleted();
+      }
+      if (unlistenNotification) {
+        unlistenNotification();
+      }
+      if (unlistenAuthor) {
+        unlistenAuthor();
+      }
+      if (unlistenNip05) {
+        unlistenNip05();
+      }
+      if (unlistenRelayList) {
+        unlistenRelayList();
+      }
+      if (unlistenRelayStatus) {
+        unlistenRelayStatus();
+      }
+      if (unlistenSwitchFailed) {
+        unlistenSwitchFailed();
+      }
+      if (unlistenIdentity) {
+        unlistenIdentity();
+      }
     };
-  }, [addMessage]);
+  }, [addMessage, locked]);
+
+  // 表示設定を読み込み（設定画面を閉じたときにも再読み込み）
+  useEffect(() => {
+    if (showSettings) return;
+    invoke<{ content_warning_mode: "hide" | "blur" | "show" }>("get_settings")
+      .then((settings) => setContentWarningMode(settings.content_warning_mode))
+      .catch(console.error);
+  }, [showSettings]);
 
   // 自動スクロール
   useEffect(() => {
@@ -140,7 +322,7 @@ function App() {
       // ローカルに即座に表示（楽観的UI更新）
       const newMessage: Message = {
         id: eventId,
-        pubkey: "self",
+        pubkey: myPubkey ?? "self",
         author: "あなた",
         content,
         timestamp: Math.floor(Date.now() / 1000),
@@ -155,6 +337,30 @@ function App() {
     }
   };
 
+  const handleUnlock = async (e: React.FormEvent) => {
+    e.preventDefault();
+    try {
+      await invoke<string>("unlock_keys", { passphrase });
+      setPassphrase("");
+      setLocked(false);
+    } catch (e) {
+      console.error("Unlock error:", e);
+    }
+  };
+
+  const handleSwitchUnlock = async (e: React.FormEvent) => {
+    e.preventDefault();
+    if (!switchFailure) return;
+    try {
+      await invoke<string>("switch_identity", { id: switchFailure.id, passphrase: switchPassphrase });
+      setSwitchFailure(null);
+    } catch (e) {
+      setSwitchFailure({ ...switchFailure, message: String(e) });
+    } finally {
+      setSwitchPassphrase("");
+    }
+  };
+
   const handleKeyDown = (e: React.KeyboardEvent) => {
     if (e.key === "Enter" && e.shiftKey) {
       e.preventDefault();
@@ -162,10 +368,33 @@ function App() {
     }
   };
 
-  const handleContextMenu = (e: React.MouseEvent, pubkey: string, author: string) => {
-    if (pubkey === "self") return; // 自分のメッセージは除外
+  // 送信直後に表示したもの（"self"）と、リレーから届いた自分のメッセージ
+  const isMine = (pubkey: string) => pubkey === "self" || (myPubkey !== null && pubkey === myPubkey);
+
+  const handleContextMenu = (e: React.MouseEvent, id: string, pubkey: string, author: string) => {
     e.preventDefault();
-    setContextMenu({ x: e.clientX, y: e.clientY, pubkey, author });
+    setContextMenu({ x: e.clientX, y: e.clientY, id, pubkey, author });
+  };
+
+  const handleDelete = async () => {
+    if (!contextMenu) return;
+    try {
+      await invoke("delete_message", { eventId: contextMenu.id, reason: null });
+      setMessages((prev) => prev.filter((m) => m.id !== contextMenu.id));
+    } catch (e) {
+      console.error("Delete error:", e);
+    }
+    setContextMenu(null);
+  };
+
+  const handleRepost = async () => {
+    if (!contextMenu) return;
+    try {
+      await invoke("repost", { eventId: contextMenu.id });
+    } catch (e) {
+      console.error("Repost error:", e);
+    }
+    setContextMenu(null);
   };
 
   const handleMute = async () => {
@@ -184,12 +413,80 @@ function App() {
     <div className="overlay" onClick={() => setContextMenu(null)}>
       <div className="status-bar">
         <div className="status-left">
-          {status === "connecting" && <span className="status connecting">接続中...</span>}
+          {locked && <span className="status connecting">🔒 ロック中</span>}
+          {!locked && status === "connecting" && <span className="status connecting">接続中...</span>}
           {status === "connected" && <span className="status connected">● 接続済</span>}
           {status === "error" && <span className="status error">× 接続エラー</span>}
         </div>
+        {unread > 0 && (
+          <button
+            className="unread-badge"
+            onClick={() => invoke<number>("mark_read").then(setUnread).catch(console.error)}
+          >
+            🔔 {unread}
+          </button>
+        )}
         <button className="settings-btn" onClick={() => setShowSettings(true)}>⚙</button>
       </div>
+      {relayOffer && (
+        <div className="relay-offer">
+          <span>
+            📡 公開済みのリレーリストに {relayOffer.relays.length} 件のリレーがあります
+          </span>
+          <button
+            onClick={() =>
+              invoke("merge_relay_list").catch(console.error).finally(() => setRelayOffer(null))
+            }
+          >
+            追加
+          </button>
+          <button
+            onClick={() =>
+              invoke("dismiss_relay_list").catch(console.error).finally(() => setRelayOffer(null))
+            }
+          >
+            無視
+          </button>
+        </div>
+      )}
+      {authRequests.length > 0 && (
+        <div className="relay-offer">
+          <span>🔑 {displayHost(authRequests[0])} が認証を求めています</span>
+          <button
+            onClick={() =>
+              invoke("respond_relay_auth", { url: authRequests[0], allow: true }).catch(console.error)
+            }
+          >
+            認証する
+          </button>
+          <button
+            onClick={() =>
+              invoke("respond_relay_auth", { url: authRequests[0], allow: false }).catch(console.error)
+            }
+          >
+            しない
+          </button>
+        </div>
+      )}
+      {switchFailure && (
+        <div className="relay-offer">
+          {switchFailure.locked ? (
+            <form onSubmit={handleSwitchUnlock} className="switch-unlock">
+              <span title={switchFailure.message}>🔒 {switchFailure.name} に切り替えるにはパスフレーズが必要です</span>
+              <input
+                type="password"
+                value={switchPassphrase}
+                onChange={(e) => setSwitchPassphrase(e.target.value)}
+                placeholder="パスフレーズを入力して Enter"
+                autoFocus
+              />
+            </form>
+          ) : (
+            <span>⚠️ {switchFailure.name}: {switchFailure.message}</span>
+          )}
+          <button onClick={() => setSwitchFailure(null)}>閉じる</button>
+        </div>
+      )}
       {showSettings && <Settings onClose={() => setShowSettings(false)} />}
       {contextMenu && (
         <div
@@ -197,25 +494,71 @@ function App() {
           style={{ left: contextMenu.x, top: contextMenu.y }}
           onClick={(e) => e.stopPropagation()}
         >
-          <button onClick={handleMute}>🔇 {contextMenu.author} をミュート</button>
+          {isMine(contextMenu.pubkey) ? (
+            <button onClick={handleDelete}>🗑️ 取り消す</button>
+          ) : (
+            <>
+              <button onClick={handleRepost}>🔁 リポスト</button>
+              <button onClick={handleMute}>🔇 {contextMenu.author} をミュート</button>
+            </>
+          )}
         </div>
       )}
       <div className="messages">
         {messages.map((msg) => (
           <div
             key={msg.id}
-            className={`message ${msg.pubkey === "self" ? "mine" : ""}`}
-            onContextMenu={(e) => handleContextMenu(e, msg.pubkey, msg.author)}
+            className={`message ${isMine(msg.pubkey) ? "mine" : ""}`}
+            onContextMenu={(e) => handleContextMenu(e, msg.id, msg.pubkey, msg.author)}
           >
+            {msg.reposted_by && (
+              <span className="repost-label">
+                🔁 {msg.reposted_by}
+                {msg.repost_count && msg.repost_count > 1 ? ` 他${msg.repost_count - 1}人` : ""}
+              </span>
+            )}
             <span className="time">{formatTime(msg.timestamp)}</span>
             {msg.context && <span className="context-label">{msg.context}</span>}
             {msg.is_post && <span className="post-label">[投稿]</span>}
-            <span className="author">{msg.author}:</span>
-            <span className="content">{linkify(msg.content)}</span>
+            <span className="author">
+              {msg.author}
+              {msg.verified_nip05 && (
+                <span className="nip05-verified" title={msg.verified_nip05}>
+                  ✓
+                </span>
+              )}
+              {msg.look_alike_name && (
+                <span className="look-alike" title="フォロー中のユーザーと似た名前です">
+                  ⚠️{msg.short_npub}
+                </span>
+              )}
+              :
+            </span>
+            {msg.content_warning != null && contentWarningMode !== "show" && !revealed.has(msg.id) ? (
+              <span
+                className="content content-warning"
+                onClick={() => setRevealed((prev) => new Set(prev).add(msg.id))}
+              >
+                ⚠️ {msg.content_warning || "注意が必要な内容"}（クリックで表示）
+              </span>
+            ) : (
+              <span className="content">{renderContent(msg)}</span>
+            )}
           </div>
         ))}
         <div ref={messagesEndRef} />
       </div>
+      {locked && (
+        <form onSubmit={handleUnlock} className="input-area">
+          <input
+            type="password"
+            value={passphrase}
+            onChange={(e) => setPassphrase(e.target.value)}
+            placeholder="パスフレーズを入力して Enter"
+            autoFocus
+          />
+        </form>
+      )}
       <form onSubmit={handleSubmit} className="input-area">
         <input
           type="text"
@@ -1,5 +1,6 @@
 import { useState, useEffect } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import "./Settings.css";
 
 interface Profile {
@@ -9,6 +10,57 @@ interface Profile {
   picture: string | null;
   website: string | null;
   nip05: string | null;
+  banner?: string | null;
+  bot?: boolean;
+  created_at?: number;
+  extra?: Record<string, unknown>;
+}
+
+interface RelayEntry {
+  url: string;
+  read: boolean;
+  write: boolean;
+}
+
+interface RelayInfo {
+  name?: string | null;
+  description?: string | null;
+  supported_nips: number[];
+  limitation?: {
+    max_message_length?: number | null;
+    max_content_length?: number | null;
+    auth_required?: boolean | null;
+    payment_required?: boolean | null;
+    restricted_writes?: boolean | null;
+  } | null;
+  payments_url?: string | null;
+}
+
+type AuthPolicy = "always" | "ask" | "never";
+
+interface RelayStatus {
+  url: string;
+  connection: string;
+  auth: "not_required" | "awaiting_approval" | "authenticating" | "authenticated" | "declined" | "failed";
+  message: string | null;
+}
+
+const AUTH_STATE_LABELS: Record<RelayStatus["auth"], string> = {
+  not_required: "",
+  awaiting_approval: "認証待ち",
+  authenticating: "認証中",
+  authenticated: "認証済",
+  declined: "認証せず",
+  failed: "認証失敗",
+};
+
+type ContentWarningMode = "hide" | "blur" | "show";
+
+interface AppSettings {
+  content_warning_mode: ContentWarningMode;
+  profile_refresh_hours: number;
+  hide_bots: boolean;
+  relay_auth: Record<string, AuthPolicy>;
 }
 
 interface SettingsProps {
@@ -22,6 +74,15 @@ export default function Settings({ onClose }: SettingsProps) {
   const [importKey, setImportKey] = useState("");
   const [importStatus, setImportStatus] = useState<"idle" | "success" | "error">("idle");
   const [importMessage, setImportMessage] = useState("");
+  const [bunkerUri, setBunkerUri] = useState("");
+  const [bunkerMessage, setBunkerMessage] = useState("");
+
+  // パスフレーズ
+  const [keyEncrypted, setKeyEncrypted] = useState(false);
+  const [mnemonicPending, setMnemonicPending] = useState(false);
+  const [mnemonic, setMnemonic] = useState("");
+  const [newPassphrase, setNewPassphrase] = useState("");
+  const [passphraseMessage, setPassphraseMessage] = useState("");
 
   // プロフィール編集
   const [profileName, setProfileName] = useState("");
@@ -30,31 +91,103 @@ export default function Settings({ onClose }: SettingsProps) {
   const [profilePicture, setProfilePicture] = useState("");
   const [profileWebsite, setProfileWebsite] = useState("");
   const [profileNip05, setProfileNip05] = useState("");
+  const [loadedProfile, setLoadedProfile] = useState<Profile | null>(null);
   const [profileStatus, setProfileStatus] = useState<"idle" | "saving" | "success" | "error">("idle");
 
+  // 表示設定
+  const [contentWarningMode, setContentWarningMode] = useState<ContentWarningMode>("blur");
+  const [profileRefreshHours, setProfileRefreshHours] = useState(24);
+  const [hideBots, setHideBots] = useState(false);
+
   // リレー管理
-  const [relays, setRelays] = useState<string[]>([]);
+  const [relays, setRelays] = useState<RelayEntry[]>([]);
   const [newRelay, setNewRelay] = useState("");
+  const [relayMessage, setRelayMessage] = useState("");
+  const [relayInfo, setRelayInfo] = useState<Record<string, RelayInfo>>({});
+  const [relayStatuses, setRelayStatuses] = useState<Record<string, RelayStatus>>({});
+  const [relayAuth, setRelayAuth] = useState<Record<string, AuthPolicy>>({});
+  const [addingRelay, setAddingRelay] = useState(false);
 
   useEffect(() => {
     invoke<string>("get_public_key").then(setPubkey).catch(console.error);
+    invoke<{ encrypted: boolean; mnemonic_pending: boolean }>("get_key_status")
+      .then((status) => {
+        setKeyEncrypted(status.encrypted);
+        setMnemonicPending(status.mnemonic_pending);
+      })
+      .catch(console.error);
 
-    // 既存のプロフィールを読み込み
+    // 既存のプロフィールを読み込み（起動直後は取得が終わってから届く）
+    const applyProfile = (profile: Profile) => {
+      setLoadedProfile(profile);
+      setProfileName(profile.name || "");
+      setProfileDisplayName(profile.display_name || "");
+      setProfileAbout(profile.about || "");
+      setProfilePicture(profile.picture || "");
+      setProfileWebsite(profile.website || "");
+      setProfileNip05(profile.nip05 || "");
+    };
     invoke<Profile | null>("get_my_profile").then((profile) => {
       if (profile) {
-        setProfileName(profile.name || "");
-        setProfileDisplayName(profile.display_name || "");
-        setProfileAbout(profile.about || "");
-        setProfilePicture(profile.picture || "");
-        setProfileWebsite(profile.website || "");
-        setProfileNip05(profile.nip05 || "");
+        applyProfile(profile);
       }
     }).catch(console.error);
+    const unlistenProfile = listen<Profile>("my-profile-updated", (event) => applyProfile(event.payload));
 
     // リレーリストを読み込み
-    invoke<string[]>("get_relays").then(setRelays).catch(console.error);
+    invoke<RelayEntry[]>("get_relays").then(setRelays).catch(console.error);
+
+    // リレーの接続・認証の状態を読み込み、変化を反映
+    invoke<RelayStatus[]>("get_relay_statuses")
+      .then((statuses) => setRelayStatuses(Object.fromEntries(statuses.map((status) => [status.url, status]))))
+      .catch(console.error);
+    const unlistenRelayStatus = listen<RelayStatus>("relay-status", (event) =>
+      setRelayStatuses((prev) => ({ ...prev, [event.payload.url]: event.payload }))
+    );
+
+    // 表示設定を読み込み
+    invoke<AppSettings>("get_settings")
+      .then((settings) => {
+        setContentWarningMode(settings.content_warning_mode);
+        setProfileRefreshHours(settings.profile_refresh_hours);
+        setHideBots(settings.hide_bots);
+        setRelayAuth(settings.relay_auth);
+      })
+      .catch(console.error);
+
+    return () => {
+      unlistenProfile.then((fn) => fn());
+      unlistenRelayStatus.then((fn) => fn());
+    };
   }, []);
 
+  const handleContentWarningModeChange = async (mode: ContentWarningMode) => {
+    try {
+      await invoke("set_content_warning_mode", { mode });
+      setContentWarningMode(mode);
+    } catch (e) {
+      console.error("Settings error:", e);
+    }
+  };
+
+  const handleHideBotsChange = async (hide: boolean) => {
+    try {
+      await invoke("set_hide_bots", { hide });
+      setHideBots(hide);
+    } catch (e) {
+      console.error("Settings error:", e);
+    }
+  };
+
+  const handleProfileRefreshHoursChange = async (hours: number) => {
+    try {
+      await invoke("set_profile_refresh_hours", { hours });
+      setProfileRefreshHours(hours);
+    } catch (e) {
+      console.error("Settings error:", e);
+    }
+  };
+
   const handleExport = async () => {
     if (showSecretKey) {
       setShowSecretKey(false);
@@ -78,28 +211,109 @@ export default function Settings({ onClose }: SettingsProps) {
       setPubkey(newPubkey);
       setImportKey("");
       setImportStatus("success");
-      setImportMessage("インポート成功！再起動してください");
+      setImportMessage("インポート成功！");
     } catch (e) {
       setImportStatus("error");
       setImportMessage(String(e));
     }
   };
 
+  const handleShowMnemonic = async () => {
+    try {
+      const words = await invoke<string>("show_mnemonic");
+      setMnemonic(words);
+      setMnemonicPending(false);
+    } catch (e) {
+      console.error("Mnemonic error:", e);
+    }
+  };
+
+  const handleConnectBunker = async () => {
+    if (!bunkerUri.trim()) return;
+    setBunkerMessage("署名サーバーの承認を待っています…");
+    try {
+      const newPubkey = await invoke<string>("connect_bunker", { uri: bunkerUri.trim() });
+      setPubkey(newPubkey);
+      setBunkerUri("");
+      setBunkerMessage("リモート署名に切り替えました");
+    } catch (e) {
+      setBunkerMessage(String(e));
+    }
+  };
+
+  const handleCreateNostrConnectUri = async () => {
+    try {
+      const uri = await invoke<string>("create_nostrconnect_uri");
+      setBunkerUri(uri);
+      copyToClipboard(uri);
+      setBunkerMessage("URIをコピーしました。署名アプリに貼り付けてから接続してください");
+    } catch (e) {
+      setBunkerMessage(String(e));
+    }
+  };
+
+  const handleSetPassphrase = async () => {
+    if (!newPassphrase) return;
+    try {
+      await invoke("set_passphrase", { passphrase: newPassphrase });
+      setNewPassphrase("");
+      setKeyEncrypted(true);
+      setPassphraseMessage("パスフレーズを設定しました");
+    } catch (e) {
+      setPassphraseMessage(String(e));
+    }
+  };
+
+  const handleExportEncrypted = async () => {
+    try {
+      const key = await invoke<string>("export_encrypted_secret_key");
+      setSecretKey(key);
+      setShowSecretKey(true);
+    } catch (e) {
+      console.error("Export error:", e);
+    }
+  };
+
   const copyToClipboard = (text: string) => {
     navigator.clipboard.writeText(text);
   };
 
   const handleSaveProfile = async () => {
     setProfileStatus("saving");
-    try {
-      await invoke("update_profile", {
+    const fields: [keyof Profile, string][] = [
+      ["name", profileName],
+      ["display_name", profileDisplayName],
+      ["about", profileAbout],
+      ["picture", profilePicture],
+      ["website", profileWebsite],
+      ["nip05", profileNip05],
+    ];
+    // 読み込んだときに値があって、空にしたフィールドだけ削除する
+    const clear = fields.filter(([key, value]) => !value && loadedProfile?.[key]).map(([key]) => key);
+    const save = (newProfile: boolean) =>
+      invoke("update_profile", {
         name: profileName || null,
         displayName: profileDisplayName || null,
         about: profileAbout || null,
         picture: profilePicture || null,
         website: profileWebsite || null,
         nip05: profileNip05 || null,
+        clear,
+        newProfile,
       });
+    try {
+      try {
+        await save(false);
+      } catch (e) {
+        // 今のプロフィールが見つからないときは、上書きしてよいか確かめてから新しく作る
+        if (!String(e).includes("No existing profile found")) throw e;
+        if (!window.confirm("公開済みのプロフィールが見つかりませんでした。新しいプロフィールとして保存しますか？（他のクライアントで設定した項目は消えます）")) {
+          setProfileStatus("idle");
+          return;
+        }
+        await save(true);
+      }
+      setLoadedProfile(Object.fromEntries(fields.map(([key, value]) => [key, value || null])) as unknown as Profile);
       setProfileStatus("success");
       setTimeout(() => setProfileStatus("idle"), 2000);
     } catch (e) {
@@ -108,30 +322,65 @@ export default function Settings({ onClose }: SettingsProps) {
     }
   };
 
+  // リレーの情報（NIP-11）を読み込み（取得できないリレーは表示しない）
+  useEffect(() => {
+    relays
+      .filter((relay) => !(relay.url in relayInfo))
+      .forEach((relay) => {
+        invoke<RelayInfo>("get_relay_info", { url: relay.url })
+          .then((info) => setRelayInfo((prev) => ({ ...prev, [relay.url]: info })))
+          .catch(console.error);
+      });
+  }, [relays]);
+
   const handleAddRelay = async () => {
     if (!newRelay.trim()) return;
-    let url = newRelay.trim();
-    if (!url.startsWith("wss://") && !url.startsWith("ws://")) {
-      url = "wss://" + url;
-    }
+    // wss:// の補完と正規化はバックエンドで行い、接続できることを確かめてから追加
+    setAddingRelay(true);
+    setRelayMessage("接続を確認しています...");
     try {
-      await invoke("add_relay", { url });
-      setRelays([...relays, url]);
+      const added = await invoke<RelayEntry>("add_relay", { url: newRelay.trim() });
+      setRelays([...relays, added]);
       setNewRelay("");
+      setRelayMessage("");
     } catch (e) {
       console.error("Add relay error:", e);
+      setRelayMessage(String(e));
+    } finally {
+      setAddingRelay(false);
     }
   };
 
   const handleRemoveRelay = async (url: string) => {
     try {
       await invoke("remove_relay", { url });
-      setRelays(relays.filter((r) => r !== url));
+      setRelays(relays.filter((r) => r.url !== url));
     } catch (e) {
       console.error("Remove relay error:", e);
     }
   };
 
+  const handleRelayAuthChange = async (url: string, policy: AuthPolicy) => {
+    try {
+      await invoke("set_relay_auth_policy", { url, policy });
+      setRelayAuth((prev) => ({ ...prev, [url]: policy }));
+    } catch (e) {
+      console.error("Relay auth error:", e);
+    }
+  };
+
+  // read / write のどちらかは残す
+  const handleToggleRelay = async (relay: RelayEntry, marker: "read" | "write") => {
+    const updated = { ...relay, [marker]: !relay[marker] };
+    if (!updated.read && !updated.write) return;
+    try {
+      await invoke("set_relay_markers", updated);
+      setRelays(relays.map((r) => (r.url === relay.url ? updated : r)));
+    } catch (e) {
+      console.error("Update relay error:", e);
+    }
+  };
+
   return (
     <div className="settings-overlay" onClick={onClose}>
       <div className="settings-panel" onClick={(e) => e.stopPropagation()}>
@@ -167,13 +416,54 @@ export default function Settings({ onClose }: SettingsProps) {
               </div>
             )}
 
+            {mnemonicPending && (
+              <div className="import-section">
+                <p className="hint">鍵は12個の単語から作られています。紙に書き写して保管してください（表示は一度だけ）</p>
+                <button className="action-btn" onClick={handleShowMnemonic}>
+                  バックアップ用の単語を表示
+                </button>
+              </div>
+            )}
+
+            {mnemonic && (
+              <div className="secret-key-box">
+                <code className="secret-key">{mnemonic}</code>
+                <p className="copy-hint">この画面を閉じると二度と表示されません</p>
+              </div>
+            )}
+
             <div className="import-section">
-              <p className="hint">既存の鍵をインポート（nsec形式）</p>
+              <p className="hint">
+                {keyEncrypted ? "🔒 パスフレーズで保護されています" : "パスフレーズで鍵を保護できます"}
+              </p>
+              {keyEncrypted ? (
+                <button className="action-btn" onClick={handleExportEncrypted}>
+                  暗号化した鍵を表示
+                </button>
+              ) : (
+                <>
+                  <input
+                    type="password"
+                    value={newPassphrase}
+                    onChange={(e) => setNewPassphrase(e.target.value)}
+                    placeholder="パスフレーズ"
+                    className="import-input"
+                  />
+                  <button className="action-btn" onClick={handleSetPassphrase} disabled={!newPassphrase}>
+                    保護する
+                  </button>
+                </>
+              )}
+              {passphraseMessage && <p className="import-status">{passphraseMessage}</p>}
+            </div>
+
+            <div className="import-section">
+              <p className="hint">既存の鍵をインポート（nsec形式、または12/24語のフレーズ）</p>
               <input
                 type="password"
                 value={importKey}
                 onChange={(e) => setImportKey(e.target.value)}
-                placeholder="nsec1..."
+                placeholder="nsec1... / abandon ability able ..."
                 className="import-input"
               />
               <button className="action-btn" onClick={handleImport} disabled={!importKey.trim()}>
@@ -183,6 +473,24 @@ export default function Settings({ onClose }: SettingsProps) {
                 <p className={`import-status ${importStatus}`}>{importMessage}</p>
               )}
             </div>
+
+            <div className="import-section">
+              <p className="hint">リモート署名（NIP-46）: 秘密鍵をこのPCに置かずに使う</p>
+              <input
+                type="text"
+                value={bunkerUri}
+                onChange={(e) => setBunkerUri(e.target.value)}
+                placeholder="bunker://... または nostrconnect://..."
+                className="import-input"
+              />
+              <button className="action-btn" onClick={handleConnectBunker} disabled={!bunkerUri.trim()}>
+                接続
+              </button>
+              <button className="action-btn" onClick={handleCreateNostrConnectUri}>
+                nostrconnect URIを発行
+              </button>
+              {bunkerMessage && <p className="hint">{bunkerMessage}</p>}
+            </div>
           </section>
 
           <section>
@@ -253,14 +561,98 @@ export default function Settings({ onClose }: SettingsProps) {
             </div>
           </section>
 
+          <section>
+            <h3>注意が必要な内容</h3>
+            <p className="hint">ネタバレや刺激の強い投稿の表示方法</p>
+            <select
+              value={contentWarningMode}
+              onChange={(e) => handleContentWarningModeChange(e.target.value as ContentWarningMode)}
+            >
+              <option value="hide">表示しない</option>
+              <option value="blur">隠して表示（クリックで表示）</option>
+              <option value="show">そのまま表示</option>
+            </select>
+          </section>
+
+          <section>
+            <h3>bot</h3>
+            <label>
+              <input type="checkbox" checked={hideBots} onChange={(e) => handleHideBotsChange(e.target.checked)} />
+              bot のアカウントの投稿を表示しない
+            </label>
+          </section>
+
+          <section>
+            <h3>プロフィールの更新</h3>
+            <p className="hint">保存したプロフィールを取り直すまでの時間</p>
+            <select
+              value={profileRefreshHours}
+              onChange={(e) => handleProfileRefreshHoursChange(Number(e.target.value))}
+            >
+              <option value={6}>6時間</option>
+              <option value={24}>1日</option>
+              <option value={72}>3日</option>
+              <option value={168}>1週間</option>
+            </select>
+          </section>
+
           <section>
             <h3>接続先リレー</h3>
-            <p className="hint">メッセージを送受信するサーバー</p>
+            <p className="hint">メッセージを送受信するサーバー（変更するとリレーリストとして公開されます）</p>
             <ul className="relay-list">
               {relays.map((relay) => (
-                <li key={relay}>
-                  <span>{relay.replace("wss://", "")}</span>
-                  <button className="remove-btn" onClick={() => handleRemoveRelay(relay)}>×</button>
+                <li key={relay.url}>
+                  <span className="relay-url" title={relayInfo[relay.url]?.description ?? undefined}>
+                    {relay.url.replace("wss://", "")}
+                    {relayInfo[relay.url]?.name && (
+                      <span className="relay-name">{relayInfo[relay.url].name}</span>
+                    )}
+                    {relayInfo[relay.url]?.limitation?.payment_required && (
+                      <span className="relay-badge" title={relayInfo[relay.url].payments_url ?? undefined}>
+                        有料
+                      </span>
+                    )}
+                    {relayInfo[relay.url]?.limitation?.auth_required && <span className="relay-badge">認証</span>}
+                    {relayInfo[relay.url]?.limitation?.restricted_writes && (
+                      <span className="relay-badge">書込制限</span>
+                    )}
+                  </span>
+                  <span className="relay-markers">
+                    {relayStatuses[relay.url] && AUTH_STATE_LABELS[relayStatuses[relay.url].auth] && (
+                      <span
+                        className="relay-badge"
+                        title={relayStatuses[relay.url].message ?? relayStatuses[relay.url].connection}
+                      >
+                        {AUTH_STATE_LABELS[relayStatuses[relay.url].auth]}
+                      </span>
+                    )}
+                    <select
+                      value={relayAuth[relay.url] ?? "ask"}
+                      onChange={(e) => handleRelayAuthChange(relay.url, e.target.value as AuthPolicy)}
+                      title="認証（NIP-42）を求められたとき"
+                    >
+                      <option value="always">認証: 常に</option>
+                      <option value="ask">認証: 確認</option>
+                      <option value="never">認証: しない</option>
+                    </select>
+                    <label>
+                      <input
+                        type="checkbox"
+                        checked={relay.read}
+                        onChange={() => handleToggleRelay(relay, "read")}
+                      />
+                      読
+                    </label>
+                    <label>
+                      <input
+                        type="checkbox"
+                        checked={relay.write}
+                        onChange={() => handleToggleRelay(relay, "write")}
+                      />
+                      書
+                    </label>
+                    <button className="remove-btn" onClick={() => handleRemoveRelay(relay.url)}>×</button>
+                  </span>
                 </li>
               ))}
             </ul>
@@ -272,10 +664,11 @@ export default function Settings({ onClose }: SettingsProps) {
                 placeholder="relay.example.com"
                 onKeyDown={(e) => e.key === "Enter" && handleAddRelay()}
               />
-              <button className="action-btn" onClick={handleAddRelay} disabled={!newRelay.trim()}>
+              <button className="action-btn" onClick={handleAddRelay} disabled={!newRelay.trim() || addingRelay}>
                 追加
               </button>
             </div>
+            {relayMessage && <p className="hint">{relayMessage}</p>}
           </section>
 
           <section>